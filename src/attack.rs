@@ -0,0 +1,218 @@
+//! Attackers, to show which attacks the protocol resists.
+//!
+//! A [`Recorder`] sits in the ether next to a car and its keychain and keeps every frame it
+//! hears, to be played back later.
+//!
+//! The other attackers get between the two: the keychain is out of the car's reach, or the
+//! attacker jams the car's receiver, and the car hears only what the attacker transmits.
+//! [`intercept`] returns a pair of [`Antenna`]s, one in the keychain's ether and one in the
+//! car's, and a [`Tactic`] decides what of the frames heard by one antenna the other one
+//! transmits:
+//!
+//! - [`Forward`] relays everything, a relay attack on a keychain in its owner's pocket
+//! - [`RollJam`] jams every Command and releases the one it kept before, ending up with a
+//!   Command the car has never seen
+//! - [`Tamper`] changes the payload of every Command in flight
+//!
+//! Attackers know the framing but none of the keys.
+
+use crate::clock::Clock;
+use crate::frame;
+use crate::{MessageKind, MessageProcessor, ProtocolError};
+use std::cell::{RefCell, RefMut};
+use std::collections::VecDeque;
+use std::rc::Rc;
+use std::time::Duration;
+
+/// Keeps every frame it hears and never transmits
+#[derive(Default)]
+pub struct Recorder {
+    heard: Vec<Vec<u8>>,
+}
+
+impl Recorder {
+    pub fn new() -> Recorder {
+        Recorder::default()
+    }
+
+    /// Every frame heard so far, in order
+    pub fn heard(&self) -> &[Vec<u8>] {
+        &self.heard
+    }
+
+    /// The frames of `kind` heard so far, in order
+    pub fn recorded(&self, kind: MessageKind) -> Vec<Vec<u8>> {
+        self.heard
+            .iter()
+            .filter(|heard| kind_of(heard) == Some(kind))
+            .cloned()
+            .collect()
+    }
+}
+
+impl MessageProcessor for Recorder {
+    fn process(&mut self, message: &[u8]) -> Result<Option<Vec<u8>>, ProtocolError> {
+        self.heard.push(message.to_vec());
+        Ok(None)
+    }
+}
+
+fn kind_of(message: &[u8]) -> Option<MessageKind> {
+    frame::decode(message).ok().map(|frame| frame.kind)
+}
+
+/// Which device's ether an [`Antenna`] is in
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum Side {
+    Keychain,
+    Car,
+}
+
+/// What an attacker in the middle does with the frames it hears
+pub trait Tactic {
+    /// Handles a frame heard on `side`, returns the frames to transmit on the other side
+    fn intercept(&mut self, side: Side, frame: &[u8]) -> Vec<Vec<u8>>;
+}
+
+/// Relays every frame unchanged
+pub struct Forward;
+
+impl Tactic for Forward {
+    fn intercept(&mut self, _side: Side, frame: &[u8]) -> Vec<Vec<u8>> {
+        vec![frame.to_vec()]
+    }
+}
+
+/// Jams every Command from the keychain and lets the one jammed before through in its place.
+/// The car carries out every command but the last, which the attacker keeps for later while
+/// the owner blames the radio.
+#[derive(Default)]
+pub struct RollJam {
+    kept: Option<Vec<u8>>,
+}
+
+impl RollJam {
+    pub fn new() -> RollJam {
+        RollJam::default()
+    }
+
+    /// Takes the Command the car has not heard yet
+    pub fn take(&mut self) -> Option<Vec<u8>> {
+        self.kept.take()
+    }
+}
+
+impl Tactic for RollJam {
+    fn intercept(&mut self, side: Side, frame: &[u8]) -> Vec<Vec<u8>> {
+        if side == Side::Keychain && kind_of(frame) == Some(MessageKind::Command) {
+            return self.kept.replace(frame.to_vec()).into_iter().collect();
+        }
+        vec![frame.to_vec()]
+    }
+}
+
+/// Change a [`Tamper`] attacker makes to the payload of a Command
+pub type Edit = Box<dyn FnMut(&mut Vec<u8>)>;
+
+/// Rewrites the payload of every Command from the keychain and fixes up the checksum
+pub struct Tamper {
+    edit: Edit,
+}
+
+impl Tamper {
+    pub fn new(edit: Edit) -> Tamper {
+        Tamper { edit }
+    }
+}
+
+impl Tactic for Tamper {
+    fn intercept(&mut self, side: Side, frame: &[u8]) -> Vec<Vec<u8>> {
+        let decoded = match frame::decode(frame) {
+            Ok(decoded) if side == Side::Keychain && decoded.kind == MessageKind::Command => {
+                decoded
+            }
+            _ => return vec![frame.to_vec()],
+        };
+        let mut payload = decoded.payload.to_vec();
+        (self.edit)(&mut payload);
+        vec![frame::encode(decoded.kind, decoded.algorithm, &payload)]
+    }
+}
+
+struct Shared<T> {
+    tactic: T,
+    clock: Box<dyn Clock>,
+    latency: Duration,
+    outgoing: [VecDeque<(Duration, Vec<u8>)>; 2], // per side, frames and when they may go out
+}
+
+/// One end of an attacker in the middle, see [`intercept`]
+pub struct Antenna<T: Tactic> {
+    side: Side,
+    shared: Rc<RefCell<Shared<T>>>,
+}
+
+/// Puts an attacker following `tactic` between a keychain and its car, returns the antenna
+/// for the keychain's ether and the one for the car's. Frames take `latency` on the attacker's
+/// own link between the two, as told by `clock`.
+pub fn intercept<T: Tactic>(
+    tactic: T,
+    latency: Duration,
+    clock: Box<dyn Clock>,
+) -> (Antenna<T>, Antenna<T>) {
+    let shared = Rc::new(RefCell::new(Shared {
+        tactic,
+        clock,
+        latency,
+        outgoing: Default::default(),
+    }));
+    (
+        Antenna {
+            side: Side::Keychain,
+            shared: shared.clone(),
+        },
+        Antenna {
+            side: Side::Car,
+            shared,
+        },
+    )
+}
+
+impl<T: Tactic> Antenna<T> {
+    /// The tactic both antennas follow
+    pub fn tactic(&self) -> RefMut<'_, T> {
+        RefMut::map(self.shared.borrow_mut(), |shared| &mut shared.tactic)
+    }
+
+    /// Returns the next frame to transmit in this antenna's ether once it made it across
+    /// the attacker's link, call it periodically
+    pub fn poll(&mut self) -> Option<Vec<u8>> {
+        let mut shared = self.shared.borrow_mut();
+        let now = shared.clock.now();
+        let outgoing = &mut shared.outgoing[self.side as usize];
+        match outgoing.front() {
+            Some((due, _)) if *due <= now => outgoing.pop_front().map(|(_, frame)| frame),
+            _ => None,
+        }
+    }
+
+    /// How many frames are on their way to this antenna
+    pub fn pending(&self) -> usize {
+        self.shared.borrow().outgoing[self.side as usize].len()
+    }
+}
+
+impl<T: Tactic> MessageProcessor for Antenna<T> {
+    fn process(&mut self, message: &[u8]) -> Result<Option<Vec<u8>>, ProtocolError> {
+        let mut shared = self.shared.borrow_mut();
+        let due = shared.clock.now() + shared.latency;
+        let other = match self.side {
+            Side::Keychain => Side::Car,
+            Side::Car => Side::Keychain,
+        };
+        for frame in shared.tactic.intercept(self.side, message) {
+            shared.outgoing[other as usize].push_back((due, frame));
+        }
+        Ok(None)
+    }
+}
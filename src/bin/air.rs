@@ -0,0 +1,35 @@
+//! The shared radio medium for car and keychain processes started with `--air PATH`.
+//!
+//! Prints a line once it listens, then relays frames until killed.
+
+extern crate keychain_protocol;
+
+#[cfg(unix)]
+fn main() {
+    use keychain_protocol::transport::Air;
+    use std::env;
+    use std::process;
+
+    let path = match (env::args().nth(1), env::args().nth(2)) {
+        (Some(path), None) => path,
+        _ => {
+            eprintln!("usage: air PATH");
+            process::exit(2);
+        }
+    };
+    let air = Air::bind(&path).unwrap_or_else(|e| {
+        eprintln!("{}: {}", path, e);
+        process::exit(2);
+    });
+    println!("air listening on {}", path);
+    if let Err(e) = air.run() {
+        eprintln!("{}", e);
+        process::exit(1);
+    }
+}
+
+#[cfg(not(unix))]
+fn main() {
+    eprintln!("air needs Unix domain sockets");
+    std::process::exit(2);
+}
@@ -0,0 +1,40 @@
+//! A car listening for its keychain on a UDP port of 127.0.0.1, or on the air of an `air`
+//! broker.
+//!
+//! Prints its address, the UDP port, as its first line, then serves frames until killed. In
+//! counter mode the last counter accepted is kept in the keys directory.
+
+extern crate keychain_protocol;
+
+mod common;
+
+use common::{fail, print_log, Crypto, Options};
+use keychain_protocol::transport::serve;
+use keychain_protocol::Car;
+
+const USAGE: &str =
+    "usage: car [--keys DIR] [--generate] [--air PATH | --port PORT [--peer PORT]...] \
+                     [--mode timestamp|challenge|counter] \
+                     [--algorithm rsapss|rsapkcs1v15|ed25519|ecdsap256]";
+
+fn main() {
+    let options = Options::parse(USAGE);
+    print_log();
+    if !options.commands.is_empty() {
+        fail(USAGE);
+    }
+    let (private, keychain) = options.keys("car", "keychain");
+    let mut car = Car::<Crypto>::new(&private, &keychain, options.mode, options.algorithm)
+        .unwrap_or_else(|e| fail(e))
+        .with_counter(options.load_counter("car"));
+    let mut radio = options.radio();
+    println!("car listening at address {}", radio.address().0);
+    loop {
+        let counter = car.counter();
+        serve(&mut car, &mut *radio).unwrap_or_else(|e| fail(e));
+        if car.counter() != counter {
+            options.save_counter("car", car.counter());
+        }
+        car.poll();
+    }
+}
@@ -0,0 +1,190 @@
+//! Command line shared by the `car` and `keychain` binaries
+
+use keychain_protocol::crypto::{Algorithm, Backend};
+#[cfg(unix)]
+use keychain_protocol::transport::AirTransport;
+use keychain_protocol::transport::{Address, Transport, UdpTransport};
+use keychain_protocol::{Command, Mode};
+use std::env;
+use std::fmt::{Debug, Display};
+use std::fs;
+use std::io;
+use std::path::PathBuf;
+use std::process;
+
+#[cfg(feature = "openssl")]
+pub type Crypto = keychain_protocol::crypto::OpenSsl;
+#[cfg(not(feature = "openssl"))]
+pub type Crypto = keychain_protocol::crypto::RustCrypto;
+
+const MODES: [Mode; 3] = [Mode::Timestamp, Mode::Challenge, Mode::Counter];
+const ALGORITHMS: [Algorithm; 4] = [
+    Algorithm::RsaPss,
+    Algorithm::RsaPkcs1v15,
+    Algorithm::Ed25519,
+    Algorithm::EcdsaP256,
+];
+
+pub struct Options {
+    pub port: u16,            // 0 picks a free one
+    pub peers: Vec<Address>,  // ports broadcasts reach before they are heard from
+    pub air: Option<PathBuf>, // socket of an air broker to use instead of UDP
+    pub keys: PathBuf,        // car.pem, car.pub.pem, keychain.pem, keychain.pub.pem and counters
+    pub generate: bool,       // write fresh keys to `keys` first
+    pub mode: Mode,
+    pub algorithm: Algorithm,
+    pub commands: Vec<Command>, // positional arguments
+}
+
+// Prints what the devices log, every frame they handle
+struct Printer;
+
+impl log::Log for Printer {
+    fn enabled(&self, _: &log::Metadata) -> bool {
+        true
+    }
+
+    fn log(&self, record: &log::Record) {
+        println!("{}", record.args());
+    }
+
+    fn flush(&self) {}
+}
+
+/// Prints everything the library logs from now on
+pub fn print_log() {
+    log::set_logger(&Printer).unwrap();
+    log::set_max_level(log::LevelFilter::Debug);
+}
+
+/// Prints `message` and exits with status 2
+pub fn fail(message: impl Display) -> ! {
+    eprintln!("{}", message);
+    process::exit(2)
+}
+
+// Looks `name` up among the `Debug` names of `choices`, ignoring case
+fn named<T: Copy + Debug>(choices: &[T], name: &str) -> Option<T> {
+    choices
+        .iter()
+        .copied()
+        .find(|choice| format!("{:?}", choice).eq_ignore_ascii_case(name))
+}
+
+impl Options {
+    /// Parses the command line, exits with `usage` if it makes no sense
+    pub fn parse(usage: &str) -> Options {
+        let mut options = Options {
+            port: 0,
+            peers: Vec::new(),
+            air: None,
+            keys: PathBuf::from("."),
+            generate: false,
+            mode: Mode::Challenge,
+            algorithm: Algorithm::Ed25519,
+            commands: Vec::new(),
+        };
+        let mut args = env::args().skip(1);
+        while let Some(arg) = args.next() {
+            let mut value = || args.next().unwrap_or_else(|| fail(usage));
+            match arg.as_str() {
+                "--port" => options.port = value().parse().unwrap_or_else(|_| fail(usage)),
+                "--peer" => options
+                    .peers
+                    .push(Address(value().parse().unwrap_or_else(|_| fail(usage)))),
+                "--air" => options.air = Some(PathBuf::from(value())),
+                "--keys" => options.keys = PathBuf::from(value()),
+                "--generate" => options.generate = true,
+                "--mode" => options.mode = named(&MODES, &value()).unwrap_or_else(|| fail(usage)),
+                "--algorithm" => {
+                    options.algorithm = named(&ALGORITHMS, &value()).unwrap_or_else(|| fail(usage))
+                }
+                _ => options
+                    .commands
+                    .push(named(&Command::ALL, &arg).unwrap_or_else(|| fail(usage))),
+            }
+        }
+        options
+    }
+
+    // Writes fresh key pairs for a car and its keychain to the keys directory
+    fn generate_keys(&self) {
+        fs::create_dir_all(&self.keys).unwrap_or_else(|e| fail(e));
+        for device in &["car", "keychain"] {
+            let (public, private) = Crypto::generate(self.algorithm).unwrap_or_else(|e| fail(e));
+            self.write(&format!("{}.pub.pem", device), &public);
+            self.write(&format!("{}.pem", device), &private);
+        }
+    }
+
+    fn write(&self, name: &str, pem: &[u8]) {
+        fs::write(self.keys.join(name), pem).unwrap_or_else(|e| fail(e));
+    }
+
+    fn read(&self, name: &str) -> Vec<u8> {
+        let path = self.keys.join(name);
+        fs::read(&path).unwrap_or_else(|e| fail(format!("{}: {}", path.display(), e)))
+    }
+
+    /// Reads the private key of `device` and the public key of `peer` from the keys
+    /// directory, after writing fresh ones there if `--generate` was given
+    pub fn keys(&self, device: &str, peer: &str) -> (Vec<u8>, Vec<u8>) {
+        if self.generate {
+            self.generate_keys();
+        }
+        (
+            self.read(&format!("{}.pem", device)),
+            self.read(&format!("{}.pub.pem", peer)),
+        )
+    }
+
+    /// Last counter `device` saved to the keys directory in counter mode, 0 before the first
+    pub fn load_counter(&self, device: &str) -> u64 {
+        let path = self.keys.join(format!("{}.counter", device));
+        match fs::read_to_string(&path) {
+            Ok(counter) => counter
+                .trim()
+                .parse()
+                .unwrap_or_else(|e| fail(format!("{}: {}", path.display(), e))),
+            Err(e) if e.kind() == io::ErrorKind::NotFound => 0,
+            Err(e) => fail(format!("{}: {}", path.display(), e)),
+        }
+    }
+
+    /// Saves the last counter of `device` to the keys directory in counter mode, so the next
+    /// process carries on from it
+    pub fn save_counter(&self, device: &str, counter: u64) {
+        if self.mode == Mode::Counter {
+            self.write(
+                &format!("{}.counter", device),
+                counter.to_string().as_bytes(),
+            );
+        }
+    }
+
+    /// Connects to the air broker if one was given, otherwise binds the UDP port and adds
+    /// the peers
+    pub fn radio(&self) -> Box<dyn Transport> {
+        if let Some(air) = &self.air {
+            return air_radio(air);
+        }
+        let radio = UdpTransport::bind(self.port).unwrap_or_else(|e| fail(e));
+        Box::new(
+            self.peers
+                .iter()
+                .fold(radio, |radio, &peer| radio.with_peer(peer)),
+        )
+    }
+}
+
+#[cfg(unix)]
+fn air_radio(path: &PathBuf) -> Box<dyn Transport> {
+    let radio =
+        AirTransport::connect(path).unwrap_or_else(|e| fail(format!("{}: {}", path.display(), e)));
+    Box::new(radio)
+}
+
+#[cfg(not(unix))]
+fn air_radio(_: &PathBuf) -> Box<dyn Transport> {
+    fail("--air needs Unix domain sockets")
+}
@@ -0,0 +1,69 @@
+//! A keychain sending commands to its car over UDP on 127.0.0.1, or over the air of an `air`
+//! broker.
+//!
+//! Sends every command given on the command line in turn and waits for the car to confirm it,
+//! pressing it again as the keychain's default retry policy allows. Exits with status 1 if any
+//! was not confirmed. In counter mode the last counter sent is kept in the keys directory.
+
+extern crate keychain_protocol;
+
+mod common;
+
+use common::{fail, print_log, Crypto, Options};
+use keychain_protocol::transport::{serve, Address};
+use keychain_protocol::{Command, Keychain};
+use std::process;
+
+const USAGE: &str = "usage: keychain [--keys DIR] [--air PATH | [--port PORT] --peer PORT...] \
+                     [--mode timestamp|challenge|counter] \
+                     [--algorithm rsapss|rsapkcs1v15|ed25519|ecdsap256] [COMMAND]...";
+
+fn main() {
+    let options = Options::parse(USAGE);
+    print_log();
+    if (options.peers.is_empty() && options.air.is_none()) || options.generate {
+        fail(USAGE);
+    }
+    let (private, car) = options.keys("keychain", "car");
+    let mut keychain = Keychain::<Crypto>::new(&private, &car, options.mode, options.algorithm)
+        .unwrap_or_else(|e| fail(e))
+        .with_counter(options.load_counter("keychain"));
+    let mut radio = options.radio();
+    let commands = if options.commands.is_empty() {
+        vec![Command::Open]
+    } else {
+        options.commands.clone()
+    };
+
+    let mut all_confirmed = true;
+    for command in commands {
+        let frame = keychain.command(command).unwrap_or_else(|e| fail(e));
+        // a counter that was sent is used up, even if the process dies right after
+        options.save_counter("keychain", keychain.counter());
+        radio
+            .send(Address::BROADCAST, &frame)
+            .unwrap_or_else(|e| fail(e));
+        while keychain.waiting() {
+            serve(&mut keychain, &mut *radio).unwrap_or_else(|e| fail(e));
+            if let Some(frame) = keychain.poll().unwrap_or_else(|e| fail(e)) {
+                options.save_counter("keychain", keychain.counter());
+                radio
+                    .send(Address::BROADCAST, &frame)
+                    .unwrap_or_else(|e| fail(e));
+            }
+        }
+        if keychain.confirmed() {
+            println!(
+                "{:?} confirmed, car state: {:?}",
+                command,
+                keychain.car_state()
+            );
+        } else {
+            println!("{:?} not confirmed", command);
+            all_confirmed = false;
+        }
+    }
+    if !all_confirmed {
+        process::exit(1);
+    }
+}
@@ -0,0 +1,420 @@
+use crate::clock::{Clock, MonotonicClock};
+use crate::crypto::{Algorithm, Backend, Verifier};
+use crate::frame::{self, NO_ALGORITHM};
+use crate::freshness::{Freshness, FreshnessPolicy};
+use crate::state::{Listener, StateMachine};
+use crate::{
+    bit, constant_time_eq, from_timestamp, hex, nonce, set_bit, signed_frame, to_timestamp,
+    CarState, Command, CommandRequest, DenyReason, Message, MessageKind, MessageProcessor, Mode,
+    ProtocolError, RangeBit, RangeProof, Signature, TimeSyncRequest, COUNTER_LENGTH,
+    COUNTER_LOOK_AHEAD, NONCE_LENGTH, RANGE_MASK_LENGTH, RANGE_ROUNDS, REPLAY_CACHE_CAPACITY,
+};
+use std::convert::TryFrom;
+use std::time::Duration;
+
+/// What the car has seen of its keychain's clock, for service tools. Skews are the keychain's
+/// clock minus the car's in nanoseconds, positive when the keychain is ahead.
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub struct Diagnostics {
+    pub observed_skew: Option<i64>, // of the last authenticated timestamp, fresh or not
+    pub learned_skew: i64,          // compensation applied to the keychain's timestamps
+}
+
+// Distance bounding of an authenticated Command in progress
+struct Ranging {
+    command: Command,
+    answering: Vec<u8>, // command and token of the ranged Command, the proof is signed over them
+    round: usize,       // round whose response is awaited, RANGE_ROUNDS once it is the proof
+    sent: Duration,     // when the challenge of `round` went out
+    slowest: Duration,  // longest round trip so far
+    challenges: [u8; RANGE_MASK_LENGTH],
+    responses: [u8; RANGE_MASK_LENGTH],
+}
+
+/// Verifies Command frames from its paired keychain and carries them out
+pub struct Car<B: Backend> {
+    signer: B::Signer,     // car's own key, signs replies
+    verifier: B::Verifier, // keychain's public key
+    answering: Vec<u8>,    // command and token of the last accepted Command, signed by a Success
+    refusing: Vec<u8>,     // and of the last authenticated one, signed by refusals
+    mode: Mode,
+    permissions: Vec<Command>, // commands the keychain may send
+    state: StateMachine,
+    clock: Box<dyn Clock>,
+    challenge: Option<([u8; NONCE_LENGTH], Duration)>, // outstanding nonce and when it was issued
+    freshness: Freshness,
+    counter: u64,                     // last counter accepted from the keychain
+    resync: Option<u64>, // counter seen beyond the look-ahead window, awaiting its successor
+    max_round_trip: Option<Duration>, // of distance bounding rounds, `None` skips it
+    ranging: Option<Ranging>,
+}
+
+impl<B: Backend> Car<B> {
+    /// Creates a car signing its replies with `private_pem` that trusts the keychain
+    /// holding the private half of `keychain_pem`
+    pub fn new(
+        private_pem: &[u8],
+        keychain_pem: &[u8],
+        mode: Mode,
+        algorithm: Algorithm,
+    ) -> Result<Car<B>, ProtocolError> {
+        Ok(Car {
+            signer: B::signer(algorithm, private_pem)?,
+            verifier: B::verifier(algorithm, keychain_pem)?,
+            answering: Vec::new(),
+            refusing: Vec::new(),
+            mode,
+            permissions: Command::ALL.to_vec(),
+            state: StateMachine::new(),
+            clock: Box::new(MonotonicClock::new()),
+            challenge: None,
+            freshness: Freshness::new(FreshnessPolicy::default(), REPLAY_CACHE_CAPACITY),
+            counter: 0,
+            resync: None,
+            max_round_trip: None,
+            ranging: None,
+        })
+    }
+
+    /// Limits the keychain to `permissions`, other commands are denied
+    pub fn with_permissions(mut self, permissions: &[Command]) -> Car<B> {
+        self.permissions = permissions.to_vec();
+        self
+    }
+
+    /// Checks freshness and runs the auto-relock timer with `clock` instead of a
+    /// [`MonotonicClock`]
+    pub fn with_clock(mut self, clock: Box<dyn Clock>) -> Car<B> {
+        self.clock = clock;
+        self
+    }
+
+    /// Relocks the car when no door is opened within `after` of an Open, `None` disables
+    /// the timer. Defaults to [`AUTO_RELOCK`](crate::AUTO_RELOCK).
+    pub fn with_auto_relock(mut self, after: Option<Duration>) -> Car<B> {
+        self.state.set_auto_relock(after);
+        self
+    }
+
+    /// Carries out a Command only once the keychain answered [`RANGE_ROUNDS`] rounds of
+    /// distance bounding, each within `max_round_trip`. `None`, the default, trusts every
+    /// authenticated Command wherever it came from.
+    pub fn with_distance_bounding(mut self, max_round_trip: Option<Duration>) -> Car<B> {
+        self.max_round_trip = max_round_trip;
+        self
+    }
+
+    /// Calls `listener` with every state transition and actuator pulse
+    pub fn with_listener(mut self, listener: Listener) -> Car<B> {
+        self.state.listen(listener);
+        self
+    }
+
+    /// Continues from `counter` as the last one accepted in counter mode, as saved from
+    /// [`counter`](Car::counter)
+    pub fn with_counter(mut self, counter: u64) -> Car<B> {
+        self.counter = counter;
+        self
+    }
+
+    /// Limits how many recent Command frames are remembered in timestamp mode
+    pub fn with_replay_capacity(mut self, capacity: usize) -> Car<B> {
+        self.freshness.set_replay_capacity(capacity);
+        self
+    }
+
+    /// Replaces the default [`FreshnessPolicy`]
+    pub fn with_policy(mut self, policy: FreshnessPolicy) -> Car<B> {
+        self.freshness.policy = policy;
+        self
+    }
+
+    pub fn diagnostics(&self) -> Diagnostics {
+        Diagnostics {
+            observed_skew: self.freshness.observed_skew,
+            learned_skew: self.freshness.learned_skew,
+        }
+    }
+
+    /// Last counter accepted from the keychain in counter mode
+    pub fn counter(&self) -> u64 {
+        self.counter
+    }
+
+    /// Lock state and whether the engine is running
+    pub fn state(&self) -> CarState {
+        self.state.state()
+    }
+
+    /// Reports a door opening from the car's sensors: it stops the auto-relock timer,
+    /// or sounds the alarm if the car is armed
+    pub fn door_opened(&mut self) {
+        self.state.poll(self.clock.now());
+        self.state.door_opened();
+    }
+
+    /// Runs the auto-relock timer, call it periodically while no frames arrive
+    pub fn poll(&mut self) {
+        self.state.poll(self.clock.now());
+    }
+
+    // Checks that a frame is signed by our keychain, straight from the received bytes
+    fn verify(&self, signature: &Signature) -> Result<(), ProtocolError> {
+        self.verify_over(signature, signature.covers)
+    }
+
+    // Checks that `signed`, the bytes a signature covers and whatever it is bound to,
+    // is signed by our keychain
+    fn verify_over(&self, signature: &Signature, signed: &[u8]) -> Result<(), ProtocolError> {
+        // the algorithm byte is signed too, so it cannot be switched to a weaker one
+        match Algorithm::try_from(signature.algorithm) {
+            Ok(algorithm) if algorithm == self.verifier.algorithm() => {}
+            _ => return Err(ProtocolError::WrongAlgorithm),
+        }
+        if self.verifier.verify(signed, signature.bytes) {
+            Ok(())
+        } else {
+            Err(ProtocolError::BadSignature)
+        }
+    }
+
+    // Builds a reply bound to `answering`, the command and token of the Command it answers,
+    // `header` is the state of a Success or the reason of a Denied
+    fn reply(
+        &self,
+        kind: MessageKind,
+        header: Option<u8>,
+        answering: &[u8],
+    ) -> Result<Vec<u8>, ProtocolError> {
+        let body: Vec<u8> = header.into_iter().collect();
+        signed_frame(&self.signer, kind, &body, answering)
+    }
+
+    fn process_hello(&mut self) -> Result<Option<Vec<u8>>, ProtocolError> {
+        if self.mode != Mode::Challenge {
+            return Ok(None);
+        }
+        log::debug!("car recieved Hello");
+        let nonce = nonce::<B>();
+        self.challenge = Some((nonce, self.clock.now()));
+        // signed so that with several cars around, the keychain knows which one is ours
+        signed_frame(&self.signer, MessageKind::Challenge, &nonce, &[]).map(Some)
+    }
+
+    // Answers a signed TimeSyncRequest with the car's time, signed together with the
+    // request's nonce so the keychain can tell the answer is fresh
+    fn process_time_sync_request(
+        &mut self,
+        request: &TimeSyncRequest,
+    ) -> Result<Option<Vec<u8>>, ProtocolError> {
+        if self.mode != Mode::Timestamp {
+            return Ok(None);
+        }
+        log::debug!("car recieved TimeSyncRequest");
+        self.verify(&request.signature)?;
+        // the keychain is about to run on our time, what was learned about its own clock is void
+        self.freshness.learned_skew = 0;
+        signed_frame(
+            &self.signer,
+            MessageKind::TimeSync,
+            &to_timestamp(self.clock.now()),
+            request.nonce,
+        )
+        .map(Some)
+    }
+
+    fn process_command(
+        &mut self,
+        request: &CommandRequest,
+    ) -> Result<Option<Vec<u8>>, ProtocolError> {
+        log::debug!("car recieved Command:\n{}", hex(request.signature.covers));
+        self.verify(&request.signature)?;
+        let (command, token) = (request.command, request.token);
+        // a refusal of this frame must be bound to it, but a stale or replayed frame leaves
+        // alone what the Command being carried out or ranged is bound to
+        let answering = [&[command as u8][..], token].concat();
+        self.refusing.clone_from(&answering);
+
+        match self.mode {
+            Mode::Timestamp => {
+                let time = from_timestamp(token);
+                // not the signature, ECDSA ones can be reworked into another valid one
+                let signed = B::sha256(request.signature.covers);
+                self.freshness.check(time, signed, self.clock.now())?;
+            }
+            Mode::Challenge => {
+                // a challenge is answered at most once
+                let (nonce, issued) = self.challenge.take().ok_or(ProtocolError::NoChallenge)?;
+                match self.clock.now().checked_sub(issued) {
+                    Some(duration) if duration < self.freshness.policy.max_age => {}
+                    _ => return Err(ProtocolError::Stale),
+                }
+                if !constant_time_eq(token, &nonce) {
+                    return Err(ProtocolError::NoChallenge);
+                }
+            }
+            Mode::Counter => {
+                let mut counter = [0u8; COUNTER_LENGTH];
+                counter.copy_from_slice(token);
+                let counter = u64::from_be_bytes(counter);
+                if counter <= self.counter {
+                    return Err(ProtocolError::Replay);
+                }
+                if counter - self.counter > COUNTER_LOOK_AHEAD {
+                    // the keychain was pressed out of range too many times, only
+                    // two consecutive counters prove it is not an old recording
+                    let expected = self.resync.map(|c| c + 1);
+                    self.resync = Some(counter);
+                    if expected != Some(counter) {
+                        return Err(ProtocolError::ResyncPending);
+                    }
+                }
+                self.counter = counter;
+                self.resync = None;
+            }
+        }
+
+        // only a fresh frame from our keychain gets this far, so the denials below
+        // cannot be used to probe what a keychain is allowed to do
+        if !self.permissions.contains(&command) {
+            return Err(ProtocolError::NotPermitted);
+        }
+        if self.max_round_trip.is_some() {
+            let mut challenges = [0u8; RANGE_MASK_LENGTH];
+            B::random(&mut challenges);
+            self.ranging = Some(Ranging {
+                command,
+                answering,
+                round: 0,
+                sent: self.clock.now(),
+                slowest: Duration::default(),
+                challenges,
+                responses: [0u8; RANGE_MASK_LENGTH],
+            });
+            return Ok(Some(range_challenge(0, bit(&challenges, 0))));
+        }
+        self.answering = answering;
+        self.carry_out(command)
+    }
+
+    fn carry_out(&mut self, command: Command) -> Result<Option<Vec<u8>>, ProtocolError> {
+        let state = self.state.apply(command, self.clock.now())?;
+        self.reply(MessageKind::Success, Some(state.to_byte()), &self.answering)
+            .map(Some)
+    }
+
+    // Times the keychain's answer to the current round and starts the next one,
+    // or asks for the proof once every round is answered
+    fn process_range_response(
+        &mut self,
+        response: &RangeBit,
+    ) -> Result<Option<Vec<u8>>, ProtocolError> {
+        let now = self.clock.now();
+        let ranging = match &mut self.ranging {
+            Some(ranging) if ranging.round == response.round as usize => ranging,
+            _ => return Ok(None),
+        };
+        if ranging.round == RANGE_ROUNDS {
+            return Ok(None);
+        }
+        ranging.slowest = ranging.slowest.max(now.saturating_sub(ranging.sent));
+        set_bit(&mut ranging.responses, ranging.round, response.bit);
+        ranging.round += 1;
+        ranging.sent = now;
+        let challenge = ranging.round < RANGE_ROUNDS && bit(&ranging.challenges, ranging.round);
+        Ok(Some(range_challenge(ranging.round, challenge)))
+    }
+
+    // Carries out the ranged Command if the keychain signed for every bit it answered
+    // and every answer came back in time
+    fn process_range_proof(
+        &mut self,
+        proof: &RangeProof,
+    ) -> Result<Option<Vec<u8>>, ProtocolError> {
+        let ranging = match self.ranging.take() {
+            Some(ranging) if ranging.round == RANGE_ROUNDS => ranging,
+            other => {
+                self.ranging = other;
+                return Ok(None);
+            }
+        };
+        let signed = [
+            proof.signature.covers,
+            &ranging.answering,
+            &ranging.challenges,
+            &ranging.responses,
+        ]
+        .concat();
+        self.verify_over(&proof.signature, &signed)?;
+        self.refusing.clone_from(&ranging.answering);
+        log::debug!(
+            "car recieved RangeProof, slowest round trip: {:?}",
+            ranging.slowest
+        );
+        let mut expected = ranging.challenges;
+        for (bits, mask) in expected.iter_mut().zip(proof.mask.iter()) {
+            *bits ^= mask;
+        }
+        let answered = constant_time_eq(&ranging.responses, &expected);
+        let in_time = self
+            .max_round_trip
+            .is_some_and(|max| ranging.slowest <= max);
+        if !answered || !in_time {
+            return Err(ProtocolError::OutOfRange);
+        }
+        self.answering = ranging.answering;
+        self.carry_out(ranging.command)
+    }
+}
+
+impl<B: Backend> Car<B> {
+    /// Acts on a parsed frame, returns the frame to transmit in reply
+    pub fn handle(&mut self, message: &Message) -> Result<Option<Vec<u8>>, ProtocolError> {
+        self.state.poll(self.clock.now());
+        match message {
+            Message::Hello => self.process_hello(),
+            Message::Command(request) => self.process_command(request),
+            Message::TimeSyncRequest(request) => self.process_time_sync_request(request),
+            Message::RangeResponse(response) => self.process_range_response(response),
+            Message::RangeProof(proof) => self.process_range_proof(proof),
+            _ => Ok(None),
+        }
+    }
+}
+
+impl<B: Backend> MessageProcessor for Car<B> {
+    fn process(&mut self, message: &[u8]) -> Result<Option<Vec<u8>>, ProtocolError> {
+        let message = Message::parse(message, self.mode)?;
+        self.handle(&message)
+    }
+
+    fn refusal(&mut self, error: ProtocolError) -> Option<Vec<u8>> {
+        // frames that do not carry our keychain's valid signature are not answered,
+        // the car would otherwise reply to every stranger and every probe
+        let reason = match error {
+            ProtocolError::Stale | ProtocolError::FromFuture => DenyReason::Expired,
+            ProtocolError::Replay
+            | ProtocolError::NoChallenge
+            | ProtocolError::NotPermitted
+            | ProtocolError::OutOfRange => DenyReason::Rejected,
+            ProtocolError::ReplayCacheFull => DenyReason::Busy,
+            ProtocolError::Unavailable => DenyReason::Unavailable,
+            ProtocolError::ResyncPending => {
+                return self
+                    .reply(MessageKind::ResyncRequired, None, &self.refusing)
+                    .ok()
+            }
+            _ => return None,
+        };
+        self.reply(MessageKind::Denied, Some(reason as u8), &self.refusing)
+            .ok()
+    }
+}
+
+fn range_challenge(round: usize, bit: bool) -> Vec<u8> {
+    frame::encode(
+        MessageKind::RangeChallenge,
+        NO_ALGORITHM,
+        &[round as u8, bit as u8],
+    )
+}
@@ -0,0 +1,77 @@
+//! Where cars and keychains get the time from.
+//!
+//! Every clock counts from the Unix epoch, so timestamps made by a keychain's clock can be
+//! checked against its car's clock. Tests share one [`MockClock`] between both devices and
+//! move it by hand.
+
+use std::cell::Cell;
+use std::rc::Rc;
+use std::time::{Duration, Instant, SystemTime};
+
+/// Source of time for freshness checks and the car's timers
+pub trait Clock {
+    /// Time since the Unix epoch as far as this clock knows
+    fn now(&self) -> Duration;
+}
+
+/// Reads the wall clock every time, follows it when it is set backwards
+pub struct SystemClock;
+
+impl Clock for SystemClock {
+    fn now(&self) -> Duration {
+        SystemTime::now()
+            .duration_since(SystemTime::UNIX_EPOCH)
+            .unwrap_or_default()
+    }
+}
+
+/// Reads the wall clock once and counts on with [`Instant`], so it never goes backwards
+/// when the wall clock is changed. The default for cars and keychains.
+pub struct MonotonicClock {
+    epoch: Duration,
+    start: Instant,
+}
+
+impl MonotonicClock {
+    pub fn new() -> MonotonicClock {
+        MonotonicClock {
+            epoch: SystemClock.now(),
+            start: Instant::now(),
+        }
+    }
+}
+
+impl Default for MonotonicClock {
+    fn default() -> MonotonicClock {
+        MonotonicClock::new()
+    }
+}
+
+impl Clock for MonotonicClock {
+    fn now(&self) -> Duration {
+        self.epoch + self.start.elapsed()
+    }
+}
+
+/// Stands still until advanced by hand, clones share the same time
+#[derive(Clone, Default)]
+pub struct MockClock {
+    now: Rc<Cell<Duration>>,
+}
+
+impl MockClock {
+    /// Starts at the Unix epoch
+    pub fn new() -> MockClock {
+        MockClock::default()
+    }
+
+    pub fn advance(&self, by: Duration) {
+        self.now.set(self.now.get() + by);
+    }
+}
+
+impl Clock for MockClock {
+    fn now(&self) -> Duration {
+        self.now.get()
+    }
+}
@@ -0,0 +1,40 @@
+use crate::ProtocolError;
+use std::convert::TryFrom;
+
+/// What the keychain asks the car to do, carried in every Command frame
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum Command {
+    Open = 1,         // unlock the doors
+    Lock = 2,         // lock the doors
+    TrunkRelease = 3, // pop the trunk
+    WindowClose = 4,  // close all windows
+    EngineStart = 5,  // remote start, only while the car is locked
+    EngineStop = 6,   // stop a remotely started engine
+    Panic = 7,        // toggle the alarm
+    Locate = 8,       // flash the lights and sound the horn once
+}
+
+impl Command {
+    pub const ALL: [Command; 8] = [
+        Command::Open,
+        Command::Lock,
+        Command::TrunkRelease,
+        Command::WindowClose,
+        Command::EngineStart,
+        Command::EngineStop,
+        Command::Panic,
+        Command::Locate,
+    ];
+}
+
+impl TryFrom<u8> for Command {
+    type Error = ProtocolError;
+
+    fn try_from(value: u8) -> Result<Self, Self::Error> {
+        Command::ALL
+            .iter()
+            .copied()
+            .find(|&c| c as u8 == value)
+            .ok_or(ProtocolError::Malformed)
+    }
+}
@@ -0,0 +1,89 @@
+//! Signature algorithms and the backends that implement them.
+//!
+//! [`Car`](crate::Car) and [`Keychain`](crate::Keychain) only ever talk to the traits below,
+//! so the protocol does not care whether OpenSSL or pure Rust code does the actual math.
+
+#[cfg(feature = "openssl")]
+mod openssl;
+#[cfg(feature = "rustcrypto")]
+mod rustcrypto;
+
+#[cfg(feature = "openssl")]
+pub use self::openssl::OpenSsl;
+#[cfg(feature = "rustcrypto")]
+pub use self::rustcrypto::RustCrypto;
+
+#[cfg(not(any(feature = "openssl", feature = "rustcrypto")))]
+compile_error!("enable at least one crypto backend: `openssl` or `rustcrypto`");
+
+use std::convert::TryFrom;
+use std::error::Error;
+use std::fmt;
+
+pub const SHA256_LENGTH: usize = 32;
+pub const ED25519_SIGNATURE_LENGTH: usize = 64;
+pub const P256_SIGNATURE_LENGTH: usize = 64;
+
+/// How Command frames are signed, carried in every frame right after the kind
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum Algorithm {
+    RsaPss = 1,      // RSASSA-PSS with SHA-256, the default
+    RsaPkcs1v15 = 2, // RSASSA-PKCS1-v1_5 with SHA-256, for fobs that cannot do PSS
+    Ed25519 = 3,     // 64-byte signatures for short radio frames
+    EcdsaP256 = 4,   // ECDSA over P-256 with SHA-256, sent as 64-byte r || s
+}
+
+impl TryFrom<u8> for Algorithm {
+    type Error = ();
+
+    fn try_from(value: u8) -> Result<Self, Self::Error> {
+        match value {
+            x if x == Algorithm::RsaPss as u8 => Ok(Algorithm::RsaPss),
+            x if x == Algorithm::RsaPkcs1v15 as u8 => Ok(Algorithm::RsaPkcs1v15),
+            x if x == Algorithm::Ed25519 as u8 => Ok(Algorithm::Ed25519),
+            x if x == Algorithm::EcdsaP256 as u8 => Ok(Algorithm::EcdsaP256),
+            _ => Err(()),
+        }
+    }
+}
+
+/// A key could not be generated, parsed or used for signing
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub struct KeyError;
+
+impl fmt::Display for KeyError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "invalid key")
+    }
+}
+
+impl Error for KeyError {}
+
+/// Holds a private key, lives in the keychain
+pub trait Signer {
+    fn algorithm(&self) -> Algorithm;
+    /// Length of every signature made with this key, known before signing so it can be
+    /// framed and signed over
+    fn signature_length(&self) -> usize;
+    fn sign(&self, data: &[u8]) -> Result<Vec<u8>, KeyError>;
+}
+
+/// Holds a public key, lives in the car
+pub trait Verifier {
+    fn algorithm(&self) -> Algorithm;
+    fn verify(&self, data: &[u8], signature: &[u8]) -> bool;
+}
+
+/// Everything the protocol needs from a crypto library.
+/// Keys travel between backends as PEM: SubjectPublicKeyInfo and PKCS#8.
+pub trait Backend {
+    type Signer: Signer;
+    type Verifier: Verifier;
+
+    /// Returns public and private PEM of a freshly generated key pair
+    fn generate(algorithm: Algorithm) -> Result<(Vec<u8>, Vec<u8>), KeyError>;
+    fn signer(algorithm: Algorithm, private_pem: &[u8]) -> Result<Self::Signer, KeyError>;
+    fn verifier(algorithm: Algorithm, public_pem: &[u8]) -> Result<Self::Verifier, KeyError>;
+    fn sha256(data: &[u8]) -> [u8; SHA256_LENGTH];
+    fn random(buf: &mut [u8]);
+}
@@ -0,0 +1,181 @@
+use super::{
+    Algorithm, Backend, KeyError, Signer, Verifier, ED25519_SIGNATURE_LENGTH,
+    P256_SIGNATURE_LENGTH, SHA256_LENGTH,
+};
+use openssl::bn::BigNum;
+use openssl::ec::{EcGroup, EcKey};
+use openssl::ecdsa::EcdsaSig;
+use openssl::error::ErrorStack;
+use openssl::hash::{hash, MessageDigest};
+use openssl::nid::Nid;
+use openssl::pkey::{HasPublic, Id, PKey, Private, Public};
+use openssl::rsa::{Padding, Rsa};
+use openssl::sign::RsaPssSaltlen;
+
+const P256_SCALAR_LENGTH: usize = 32;
+
+/// Backend on top of the system OpenSSL
+pub struct OpenSsl;
+
+pub struct OpenSslSigner {
+    algorithm: Algorithm,
+    key: PKey<Private>,
+}
+
+pub struct OpenSslVerifier {
+    algorithm: Algorithm,
+    key: PKey<Public>,
+}
+
+impl From<ErrorStack> for KeyError {
+    fn from(_: ErrorStack) -> KeyError {
+        KeyError
+    }
+}
+
+// Refuses a key of another type than `algorithm` signs with, OpenSSL would only fail on it
+// once it is used
+fn checked<T: HasPublic>(algorithm: Algorithm, key: PKey<T>) -> Result<PKey<T>, KeyError> {
+    let fits = match algorithm {
+        Algorithm::RsaPss | Algorithm::RsaPkcs1v15 => key.id() == Id::RSA,
+        Algorithm::Ed25519 => key.id() == Id::ED25519,
+        Algorithm::EcdsaP256 => {
+            key.id() == Id::EC && key.ec_key()?.group().curve_name() == Some(Nid::X9_62_PRIME256V1)
+        }
+    };
+    if fits {
+        Ok(key)
+    } else {
+        Err(KeyError)
+    }
+}
+
+fn padding(algorithm: Algorithm) -> Padding {
+    match algorithm {
+        Algorithm::RsaPkcs1v15 => Padding::PKCS1,
+        _ => Padding::PKCS1_PSS,
+    }
+}
+
+impl Backend for OpenSsl {
+    type Signer = OpenSslSigner;
+    type Verifier = OpenSslVerifier;
+
+    fn generate(algorithm: Algorithm) -> Result<(Vec<u8>, Vec<u8>), KeyError> {
+        let key = match algorithm {
+            Algorithm::RsaPss | Algorithm::RsaPkcs1v15 => PKey::from_rsa(Rsa::generate(2048)?)?,
+            Algorithm::Ed25519 => PKey::generate_ed25519()?,
+            Algorithm::EcdsaP256 => {
+                let group = EcGroup::from_curve_name(Nid::X9_62_PRIME256V1)?;
+                PKey::from_ec_key(EcKey::generate(&group)?)?
+            }
+        };
+        Ok((key.public_key_to_pem()?, key.private_key_to_pem_pkcs8()?))
+    }
+
+    fn signer(algorithm: Algorithm, private_pem: &[u8]) -> Result<OpenSslSigner, KeyError> {
+        Ok(OpenSslSigner {
+            algorithm,
+            key: checked(algorithm, PKey::private_key_from_pem(private_pem)?)?,
+        })
+    }
+
+    fn verifier(algorithm: Algorithm, public_pem: &[u8]) -> Result<OpenSslVerifier, KeyError> {
+        Ok(OpenSslVerifier {
+            algorithm,
+            key: checked(algorithm, PKey::public_key_from_pem(public_pem)?)?,
+        })
+    }
+
+    fn sha256(data: &[u8]) -> [u8; SHA256_LENGTH] {
+        let mut digest = [0u8; SHA256_LENGTH];
+        digest.copy_from_slice(&hash(MessageDigest::sha256(), data).unwrap());
+        digest
+    }
+
+    fn random(buf: &mut [u8]) {
+        openssl::rand::rand_bytes(buf).unwrap();
+    }
+}
+
+impl Signer for OpenSslSigner {
+    fn algorithm(&self) -> Algorithm {
+        self.algorithm
+    }
+
+    fn signature_length(&self) -> usize {
+        match self.algorithm {
+            Algorithm::RsaPss | Algorithm::RsaPkcs1v15 => self.key.size(),
+            Algorithm::Ed25519 => ED25519_SIGNATURE_LENGTH,
+            Algorithm::EcdsaP256 => P256_SIGNATURE_LENGTH,
+        }
+    }
+
+    fn sign(&self, data: &[u8]) -> Result<Vec<u8>, KeyError> {
+        match self.algorithm {
+            Algorithm::RsaPss | Algorithm::RsaPkcs1v15 => {
+                let mut signer = openssl::sign::Signer::new(MessageDigest::sha256(), &self.key)?;
+                signer.set_rsa_padding(padding(self.algorithm))?;
+                if self.algorithm == Algorithm::RsaPss {
+                    signer.set_rsa_pss_saltlen(RsaPssSaltlen::DIGEST_LENGTH)?;
+                }
+                Ok(signer.sign_oneshot_to_vec(data)?)
+            }
+            Algorithm::Ed25519 => {
+                Ok(openssl::sign::Signer::new_without_digest(&self.key)?
+                    .sign_oneshot_to_vec(data)?)
+            }
+            Algorithm::EcdsaP256 => {
+                let der = openssl::sign::Signer::new(MessageDigest::sha256(), &self.key)?
+                    .sign_oneshot_to_vec(data)?;
+                let sig = EcdsaSig::from_der(&der)?;
+                let mut raw = vec![0u8; 2 * P256_SCALAR_LENGTH];
+                let (r, s) = (sig.r().to_vec(), sig.s().to_vec());
+                raw[P256_SCALAR_LENGTH - r.len()..P256_SCALAR_LENGTH].copy_from_slice(&r);
+                raw[2 * P256_SCALAR_LENGTH - s.len()..].copy_from_slice(&s);
+                Ok(raw)
+            }
+        }
+    }
+}
+
+impl OpenSslVerifier {
+    // Whether `signature` is valid, an error if OpenSSL could not even check it
+    fn try_verify(&self, data: &[u8], signature: &[u8]) -> Result<bool, ErrorStack> {
+        match self.algorithm {
+            Algorithm::RsaPss | Algorithm::RsaPkcs1v15 => {
+                let mut verifier =
+                    openssl::sign::Verifier::new(MessageDigest::sha256(), &self.key)?;
+                verifier.set_rsa_padding(padding(self.algorithm))?;
+                if self.algorithm == Algorithm::RsaPss {
+                    verifier.set_rsa_pss_saltlen(RsaPssSaltlen::DIGEST_LENGTH)?;
+                }
+                verifier.verify_oneshot(signature, data)
+            }
+            Algorithm::Ed25519 => openssl::sign::Verifier::new_without_digest(&self.key)?
+                .verify_oneshot(signature, data),
+            Algorithm::EcdsaP256 => {
+                if signature.len() != 2 * P256_SCALAR_LENGTH {
+                    return Ok(false);
+                }
+                let (r, s) = signature.split_at(P256_SCALAR_LENGTH);
+                let sig = EcdsaSig::from_private_components(
+                    BigNum::from_slice(r)?,
+                    BigNum::from_slice(s)?,
+                )?;
+                openssl::sign::Verifier::new(MessageDigest::sha256(), &self.key)?
+                    .verify_oneshot(&sig.to_der()?, data)
+            }
+        }
+    }
+}
+
+impl Verifier for OpenSslVerifier {
+    fn algorithm(&self) -> Algorithm {
+        self.algorithm
+    }
+
+    fn verify(&self, data: &[u8], signature: &[u8]) -> bool {
+        self.try_verify(data, signature).unwrap_or(false)
+    }
+}
@@ -0,0 +1,178 @@
+use super::{
+    Algorithm, Backend, KeyError, Signer, Verifier, ED25519_SIGNATURE_LENGTH,
+    P256_SIGNATURE_LENGTH, SHA256_LENGTH,
+};
+use rand_core::{OsRng, RngCore};
+use rsa::pkcs8::{
+    DecodePrivateKey, DecodePublicKey, EncodePrivateKey, EncodePublicKey, LineEnding,
+};
+use rsa::signature::{RandomizedSigner, SignatureEncoding, Signer as _, Verifier as _};
+use rsa::traits::PublicKeyParts;
+use rsa::{pkcs1v15, pss, RsaPrivateKey, RsaPublicKey};
+use sha2::{Digest, Sha256};
+use std::convert::TryFrom;
+use std::str;
+
+/// Backend in pure Rust, for targets without OpenSSL
+pub struct RustCrypto;
+
+pub enum RustCryptoSigner {
+    RsaPss(pss::SigningKey<Sha256>),
+    RsaPkcs1v15(pkcs1v15::SigningKey<Sha256>),
+    Ed25519(ed25519_dalek::SigningKey),
+    EcdsaP256(p256::ecdsa::SigningKey),
+}
+
+pub enum RustCryptoVerifier {
+    RsaPss(pss::VerifyingKey<Sha256>),
+    RsaPkcs1v15(pkcs1v15::VerifyingKey<Sha256>),
+    Ed25519(ed25519_dalek::VerifyingKey),
+    EcdsaP256(p256::ecdsa::VerifyingKey),
+}
+
+impl Backend for RustCrypto {
+    type Signer = RustCryptoSigner;
+    type Verifier = RustCryptoVerifier;
+
+    fn generate(algorithm: Algorithm) -> Result<(Vec<u8>, Vec<u8>), KeyError> {
+        let (public_pem, private_pem) = match algorithm {
+            Algorithm::RsaPss | Algorithm::RsaPkcs1v15 => {
+                let key = RsaPrivateKey::new(&mut OsRng, 2048).map_err(|_| KeyError)?;
+                (
+                    key.to_public_key().to_public_key_pem(LineEnding::LF),
+                    key.to_pkcs8_pem(LineEnding::LF),
+                )
+            }
+            Algorithm::Ed25519 => {
+                let key = ed25519_dalek::SigningKey::generate(&mut OsRng);
+                (
+                    key.verifying_key().to_public_key_pem(LineEnding::LF),
+                    key.to_pkcs8_pem(LineEnding::LF),
+                )
+            }
+            Algorithm::EcdsaP256 => {
+                let key = p256::ecdsa::SigningKey::random(&mut OsRng);
+                (
+                    key.verifying_key().to_public_key_pem(LineEnding::LF),
+                    key.to_pkcs8_pem(LineEnding::LF),
+                )
+            }
+        };
+        Ok((
+            public_pem.map_err(|_| KeyError)?.into_bytes(),
+            private_pem.map_err(|_| KeyError)?.as_bytes().to_vec(),
+        ))
+    }
+
+    fn signer(algorithm: Algorithm, private_pem: &[u8]) -> Result<RustCryptoSigner, KeyError> {
+        let pem = str::from_utf8(private_pem).map_err(|_| KeyError)?;
+        let signer = match algorithm {
+            Algorithm::RsaPss => RustCryptoSigner::RsaPss(pss::SigningKey::new(
+                RsaPrivateKey::from_pkcs8_pem(pem).map_err(|_| KeyError)?,
+            )),
+            Algorithm::RsaPkcs1v15 => RustCryptoSigner::RsaPkcs1v15(pkcs1v15::SigningKey::new(
+                RsaPrivateKey::from_pkcs8_pem(pem).map_err(|_| KeyError)?,
+            )),
+            Algorithm::Ed25519 => RustCryptoSigner::Ed25519(
+                ed25519_dalek::SigningKey::from_pkcs8_pem(pem).map_err(|_| KeyError)?,
+            ),
+            Algorithm::EcdsaP256 => RustCryptoSigner::EcdsaP256(
+                p256::ecdsa::SigningKey::from_pkcs8_pem(pem).map_err(|_| KeyError)?,
+            ),
+        };
+        Ok(signer)
+    }
+
+    fn verifier(algorithm: Algorithm, public_pem: &[u8]) -> Result<RustCryptoVerifier, KeyError> {
+        let pem = str::from_utf8(public_pem).map_err(|_| KeyError)?;
+        let verifier = match algorithm {
+            Algorithm::RsaPss => RustCryptoVerifier::RsaPss(pss::VerifyingKey::new(
+                RsaPublicKey::from_public_key_pem(pem).map_err(|_| KeyError)?,
+            )),
+            Algorithm::RsaPkcs1v15 => RustCryptoVerifier::RsaPkcs1v15(pkcs1v15::VerifyingKey::new(
+                RsaPublicKey::from_public_key_pem(pem).map_err(|_| KeyError)?,
+            )),
+            Algorithm::Ed25519 => RustCryptoVerifier::Ed25519(
+                ed25519_dalek::VerifyingKey::from_public_key_pem(pem).map_err(|_| KeyError)?,
+            ),
+            Algorithm::EcdsaP256 => RustCryptoVerifier::EcdsaP256(
+                p256::ecdsa::VerifyingKey::from_public_key_pem(pem).map_err(|_| KeyError)?,
+            ),
+        };
+        Ok(verifier)
+    }
+
+    fn sha256(data: &[u8]) -> [u8; SHA256_LENGTH] {
+        Sha256::digest(data).into()
+    }
+
+    fn random(buf: &mut [u8]) {
+        OsRng.fill_bytes(buf);
+    }
+}
+
+impl Signer for RustCryptoSigner {
+    fn algorithm(&self) -> Algorithm {
+        match self {
+            RustCryptoSigner::RsaPss(_) => Algorithm::RsaPss,
+            RustCryptoSigner::RsaPkcs1v15(_) => Algorithm::RsaPkcs1v15,
+            RustCryptoSigner::Ed25519(_) => Algorithm::Ed25519,
+            RustCryptoSigner::EcdsaP256(_) => Algorithm::EcdsaP256,
+        }
+    }
+
+    fn signature_length(&self) -> usize {
+        match self {
+            RustCryptoSigner::RsaPss(key) => key.as_ref().size(),
+            RustCryptoSigner::RsaPkcs1v15(key) => key.as_ref().size(),
+            RustCryptoSigner::Ed25519(_) => ED25519_SIGNATURE_LENGTH,
+            RustCryptoSigner::EcdsaP256(_) => P256_SIGNATURE_LENGTH,
+        }
+    }
+
+    fn sign(&self, data: &[u8]) -> Result<Vec<u8>, KeyError> {
+        let signature = match self {
+            RustCryptoSigner::RsaPss(key) => key
+                .try_sign_with_rng(&mut OsRng, data)
+                .map_err(|_| KeyError)?
+                .to_vec(),
+            RustCryptoSigner::RsaPkcs1v15(key) => {
+                key.try_sign(data).map_err(|_| KeyError)?.to_vec()
+            }
+            RustCryptoSigner::Ed25519(key) => key.sign(data).to_bytes().to_vec(),
+            RustCryptoSigner::EcdsaP256(key) => {
+                let signature: p256::ecdsa::Signature = key.try_sign(data).map_err(|_| KeyError)?;
+                signature.to_bytes().to_vec()
+            }
+        };
+        Ok(signature)
+    }
+}
+
+impl Verifier for RustCryptoVerifier {
+    fn algorithm(&self) -> Algorithm {
+        match self {
+            RustCryptoVerifier::RsaPss(_) => Algorithm::RsaPss,
+            RustCryptoVerifier::RsaPkcs1v15(_) => Algorithm::RsaPkcs1v15,
+            RustCryptoVerifier::Ed25519(_) => Algorithm::Ed25519,
+            RustCryptoVerifier::EcdsaP256(_) => Algorithm::EcdsaP256,
+        }
+    }
+
+    fn verify(&self, data: &[u8], signature: &[u8]) -> bool {
+        match self {
+            RustCryptoVerifier::RsaPss(key) => pss::Signature::try_from(signature)
+                .map(|s| key.verify(data, &s).is_ok())
+                .unwrap_or(false),
+            RustCryptoVerifier::RsaPkcs1v15(key) => pkcs1v15::Signature::try_from(signature)
+                .map(|s| key.verify(data, &s).is_ok())
+                .unwrap_or(false),
+            RustCryptoVerifier::Ed25519(key) => ed25519_dalek::Signature::from_slice(signature)
+                .map(|s| key.verify_strict(data, &s).is_ok())
+                .unwrap_or(false),
+            RustCryptoVerifier::EcdsaP256(key) => p256::ecdsa::Signature::from_slice(signature)
+                .map(|s| key.verify(data, &s).is_ok())
+                .unwrap_or(false),
+        }
+    }
+}
@@ -0,0 +1,67 @@
+use crate::crypto::KeyError;
+use crate::DenyReason;
+use std::error::Error;
+use std::fmt;
+
+/// Why a frame was refused or a device could not be set up
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum ProtocolError {
+    Malformed,          // frame or payload has the wrong length
+    UnknownVersion(u8), // frame speaks a protocol version we do not
+    Checksum,           // frame was damaged in transit
+    UnknownKind,        // kind byte is not a known MessageKind
+    WrongAlgorithm,     // frame is signed with another algorithm than the pair agreed on
+    Stale,              // timestamp or challenge is older than the car's policy allows
+    FromFuture,         // timestamp is further ahead of the car's clock than its policy tolerates
+    BadSignature,       // signature does not match the paired key
+    Replay,             // frame, counter or challenge was already used
+    ReplayCacheFull,    // too many fresh frames to remember, refusing until some expire
+    NoChallenge,        // response to a challenge the car never issued or already consumed
+    ResyncPending,      // counter jumped ahead, one more consecutive frame is needed
+    NotPermitted,       // keychain is not authorized for this command
+    Unavailable,        // command cannot be carried out in the car's current state
+    KeyLoad(KeyError),  // key could not be generated, parsed or used
+    Denied(DenyReason), // car refused our command
+    ResyncRequired,     // car needs another command before it accepts our counter
+    OutOfRange,         // distance bounding took too long or was answered by someone else
+}
+
+impl fmt::Display for ProtocolError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            ProtocolError::Malformed => write!(f, "malformed frame"),
+            ProtocolError::UnknownVersion(v) => write!(f, "unknown protocol version {}", v),
+            ProtocolError::Checksum => write!(f, "checksum mismatch"),
+            ProtocolError::UnknownKind => write!(f, "unknown message kind"),
+            ProtocolError::WrongAlgorithm => write!(f, "unexpected signature algorithm"),
+            ProtocolError::Stale => write!(f, "stale frame"),
+            ProtocolError::FromFuture => write!(f, "frame timestamped in the future"),
+            ProtocolError::BadSignature => write!(f, "bad signature"),
+            ProtocolError::Replay => write!(f, "replayed frame"),
+            ProtocolError::ReplayCacheFull => write!(f, "replay cache is full"),
+            ProtocolError::NoChallenge => write!(f, "no outstanding challenge"),
+            ProtocolError::ResyncPending => write!(f, "counter resynchronization pending"),
+            ProtocolError::NotPermitted => write!(f, "command not permitted"),
+            ProtocolError::Unavailable => write!(f, "command unavailable in current state"),
+            ProtocolError::KeyLoad(e) => write!(f, "key load failed: {}", e),
+            ProtocolError::Denied(reason) => write!(f, "denied by the car: {:?}", reason),
+            ProtocolError::ResyncRequired => write!(f, "car requires resynchronization"),
+            ProtocolError::OutOfRange => write!(f, "keychain out of range"),
+        }
+    }
+}
+
+impl Error for ProtocolError {
+    fn source(&self) -> Option<&(dyn Error + 'static)> {
+        match self {
+            ProtocolError::KeyLoad(e) => Some(e),
+            _ => None,
+        }
+    }
+}
+
+impl From<KeyError> for ProtocolError {
+    fn from(e: KeyError) -> ProtocolError {
+        ProtocolError::KeyLoad(e)
+    }
+}
@@ -0,0 +1,235 @@
+//! Splitting frames into packets for radios with a small MTU.
+//!
+//! ```text
+//! [id: 2][index: 1][count: 1][chunk: up to MTU - 4]
+//! ```
+//!
+//! Every frame a sender fragments gets the next id, its fragments are numbered from 0 to
+//! `count - 1`. The receiver collects them in any order, keeping the ids of every sender
+//! apart, ignores duplicates and drops frames that are not complete within
+//! [`REASSEMBLY_TIMEOUT`] of their first fragment.
+//! Fragments carry no checksum of their own, a frame put together from damaged or foreign
+//! fragments fails the [`frame`](crate::frame) checksum.
+
+use crate::clock::{Clock, MonotonicClock};
+use crate::transport::{Address, Transport};
+use crate::ProtocolError;
+use std::collections::VecDeque;
+use std::io;
+use std::time::Duration;
+
+pub const FRAGMENT_HEADER_LENGTH: usize = 4;
+/// Largest packet of the sub-GHz radio
+pub const RADIO_MTU: usize = 32;
+pub const MAX_FRAGMENTS: usize = u8::MAX as usize;
+pub const REASSEMBLY_TIMEOUT: Duration = Duration::from_millis(500);
+/// How many frames may be in reassembly at once, the oldest is dropped beyond that
+pub const REASSEMBLY_CAPACITY: usize = 8;
+
+/// Splits outgoing frames into numbered fragments
+pub struct Fragmenter {
+    mtu: usize,
+    next_id: u16,
+}
+
+impl Fragmenter {
+    /// Fragments of at most `mtu` bytes, numbered from `first_id`
+    pub fn new(mtu: usize, first_id: u16) -> Fragmenter {
+        assert!(mtu > FRAGMENT_HEADER_LENGTH, "MTU too small");
+        Fragmenter {
+            mtu,
+            next_id: first_id,
+        }
+    }
+
+    /// Splits `frame` into fragments, panics if it needs more than [`MAX_FRAGMENTS`]
+    pub fn split(&mut self, frame: &[u8]) -> Vec<Vec<u8>> {
+        let chunk_length = self.mtu - FRAGMENT_HEADER_LENGTH;
+        let count = frame.len().div_ceil(chunk_length);
+        assert!(count <= MAX_FRAGMENTS, "frame too long for the MTU");
+        let id = self.next_id.to_be_bytes();
+        self.next_id = self.next_id.wrapping_add(1);
+        frame
+            .chunks(chunk_length)
+            .enumerate()
+            .map(|(index, chunk)| {
+                let mut fragment = Vec::with_capacity(FRAGMENT_HEADER_LENGTH + chunk.len());
+                fragment.extend_from_slice(&id);
+                fragment.push(index as u8);
+                fragment.push(count as u8);
+                fragment.extend_from_slice(chunk);
+                fragment
+            })
+            .collect()
+    }
+}
+
+// A frame whose fragments are still arriving
+struct Partial {
+    from: Address,
+    id: u16,
+    started: Duration, // when the first fragment arrived
+    chunks: Vec<Option<Vec<u8>>>,
+    missing: usize,
+}
+
+/// Puts frames back together from fragments arriving in any order
+pub struct Reassembler {
+    timeout: Duration,
+    partial: VecDeque<Partial>,
+    completed: VecDeque<(Address, u16, Duration)>, // recently delivered, late duplicates are dropped
+}
+
+impl Reassembler {
+    /// Drops frames that are not complete within `timeout` of their first fragment
+    pub fn new(timeout: Duration) -> Reassembler {
+        Reassembler {
+            timeout,
+            partial: VecDeque::with_capacity(REASSEMBLY_CAPACITY),
+            completed: VecDeque::new(),
+        }
+    }
+
+    // Forgets partial frames and delivered ids older than the timeout at `now`
+    fn expire(&mut self, now: Duration) {
+        let timeout = self.timeout;
+        let expired = |started: Duration| now.saturating_sub(started) >= timeout;
+        self.partial.retain(|partial| !expired(partial.started));
+        self.completed
+            .retain(|&(_, _, delivered)| !expired(delivered));
+    }
+
+    /// Takes a fragment received from `from` at `now`, returns the frame once its last
+    /// fragment is in. Duplicates and fragments of frames already delivered return `Ok(None)`.
+    pub fn receive(
+        &mut self,
+        from: Address,
+        fragment: &[u8],
+        now: Duration,
+    ) -> Result<Option<Vec<u8>>, ProtocolError> {
+        if fragment.len() <= FRAGMENT_HEADER_LENGTH {
+            return Err(ProtocolError::Malformed);
+        }
+        let id = u16::from_be_bytes([fragment[0], fragment[1]]);
+        let (index, count) = (fragment[2] as usize, fragment[3] as usize);
+        if index >= count {
+            return Err(ProtocolError::Malformed);
+        }
+        self.expire(now);
+        if self
+            .completed
+            .iter()
+            .any(|&(sender, done, _)| (sender, done) == (from, id))
+        {
+            return Ok(None);
+        }
+
+        let position = match self
+            .partial
+            .iter()
+            .position(|partial| (partial.from, partial.id) == (from, id))
+        {
+            Some(position) => position,
+            None => {
+                if self.partial.len() >= REASSEMBLY_CAPACITY {
+                    self.partial.pop_front();
+                }
+                self.partial.push_back(Partial {
+                    from,
+                    id,
+                    started: now,
+                    chunks: vec![None; count],
+                    missing: count,
+                });
+                self.partial.len() - 1
+            }
+        };
+        let partial = &mut self.partial[position];
+        if partial.chunks.len() != count {
+            return Err(ProtocolError::Malformed);
+        }
+        let chunk = &mut partial.chunks[index];
+        if chunk.is_some() {
+            return Ok(None);
+        }
+        *chunk = Some(fragment[FRAGMENT_HEADER_LENGTH..].to_vec());
+        partial.missing -= 1;
+        if partial.missing > 0 {
+            return Ok(None);
+        }
+
+        let partial = self.partial.remove(position).expect("position is in range");
+        self.completed.push_back((from, id, now));
+        Ok(Some(
+            partial.chunks.into_iter().flatten().flatten().collect(),
+        ))
+    }
+}
+
+/// Puts a fragmenting link between a device and `transport`: frames sent go out as
+/// fragments, and fragments received are handed on once they make a whole frame, so the
+/// device itself only ever sees whole frames. Broken fragments are dropped like damaged packets.
+pub struct Fragmented<T: Transport> {
+    transport: T,
+    fragmenter: Fragmenter,
+    reassembler: Reassembler,
+    clock: Box<dyn Clock>,
+}
+
+impl<T: Transport> Fragmented<T> {
+    /// Fragments of at most `mtu` bytes numbered from `first_id`, see [`Fragmenter::new`]
+    pub fn new(transport: T, mtu: usize, first_id: u16) -> Fragmented<T> {
+        Fragmented {
+            transport,
+            fragmenter: Fragmenter::new(mtu, first_id),
+            reassembler: Reassembler::new(REASSEMBLY_TIMEOUT),
+            clock: Box::new(MonotonicClock::new()),
+        }
+    }
+
+    /// Times out incomplete frames with `clock` instead of a [`MonotonicClock`]
+    pub fn with_clock(mut self, clock: Box<dyn Clock>) -> Fragmented<T> {
+        self.clock = clock;
+        self
+    }
+
+    /// Replaces the default [`REASSEMBLY_TIMEOUT`]
+    pub fn with_timeout(mut self, timeout: Duration) -> Fragmented<T> {
+        self.reassembler = Reassembler::new(timeout);
+        self
+    }
+
+    pub fn transport(&self) -> &T {
+        &self.transport
+    }
+
+    pub fn transport_mut(&mut self) -> &mut T {
+        &mut self.transport
+    }
+}
+
+impl<T: Transport> Transport for Fragmented<T> {
+    fn address(&self) -> Address {
+        self.transport.address()
+    }
+
+    /// Sends every fragment of `frame` to `to`, panics if it needs more than [`MAX_FRAGMENTS`]
+    fn send(&mut self, to: Address, frame: &[u8]) -> io::Result<()> {
+        for fragment in self.fragmenter.split(frame) {
+            self.transport.send(to, &fragment)?;
+        }
+        Ok(())
+    }
+
+    /// Returns the next whole frame and the sender of its last fragment
+    fn receive(&mut self) -> io::Result<Option<(Address, Vec<u8>)>> {
+        while let Some((from, fragment)) = self.transport.receive()? {
+            match self.reassembler.receive(from, &fragment, self.clock.now()) {
+                Ok(Some(frame)) => return Ok(Some((from, frame))),
+                Ok(None) => {}
+                Err(e) => log::debug!("fragment dropped: {}", e),
+            }
+        }
+        Ok(None)
+    }
+}
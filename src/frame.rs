@@ -0,0 +1,136 @@
+//! The envelope every message travels in.
+//!
+//! ```text
+//! [version: 1][kind: 1][algorithm: 1][length: 1-2][payload: length][checksum: 2]
+//! ```
+//!
+//! The length is one byte below 128, otherwise two bytes big-endian with the top bit set.
+//! The checksum is CRC-16/CCITT-FALSE over everything before it and only catches radio
+//! errors, authenticity comes from the signature inside the payload. Signatures cover every
+//! frame byte before them, so neither the version, the algorithm nor the length can be
+//! changed in transit, and a receiver verifies them straight from the received bytes.
+//!
+//! Decoding is strict: an unknown version or kind, a length that does not match the frame,
+//! a non-minimal length or a wrong checksum fail before any payload is looked at.
+
+use crate::{MessageKind, ProtocolError, ALGORITHM_LENGTH};
+use std::convert::TryFrom;
+
+pub const PROTOCOL_VERSION: u8 = 1;
+pub const HEADER_LENGTH: usize = 2 + ALGORITHM_LENGTH;
+pub const CHECKSUM_LENGTH: usize = 2;
+pub const MAX_PAYLOAD_LENGTH: usize = 0x7fff;
+/// Algorithm byte of frames that carry no signature
+pub const NO_ALGORITHM: u8 = 0;
+
+const LONG_LENGTH: u8 = 0x80;
+
+/// A decoded frame borrowing its payload from the received bytes
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub struct Frame<'a> {
+    pub kind: MessageKind,
+    pub algorithm: u8,
+    pub payload: &'a [u8],
+    framed: &'a [u8], // everything before the checksum
+}
+
+impl<'a> Frame<'a> {
+    /// The version, kind and algorithm bytes that signatures start with
+    pub fn header(&self) -> [u8; HEADER_LENGTH] {
+        header(self.kind, self.algorithm)
+    }
+
+    /// The frame bytes covered by a signature that follows `body_length` bytes of payload
+    pub fn signed(&self, body_length: usize) -> &'a [u8] {
+        let start = self.framed.len() - self.payload.len();
+        &self.framed[..start + body_length.min(self.payload.len())]
+    }
+}
+
+/// The version, kind and algorithm bytes of a frame
+pub fn header(kind: MessageKind, algorithm: u8) -> [u8; HEADER_LENGTH] {
+    [PROTOCOL_VERSION, kind as u8, algorithm]
+}
+
+/// CRC-16/CCITT-FALSE
+pub fn checksum(bytes: &[u8]) -> u16 {
+    let mut crc: u16 = 0xffff;
+    for &byte in bytes {
+        crc ^= (byte as u16) << 8;
+        for _ in 0..8 {
+            crc = if crc & 0x8000 != 0 {
+                (crc << 1) ^ 0x1021
+            } else {
+                crc << 1
+            };
+        }
+    }
+    crc
+}
+
+/// Starts a frame of `kind` whose payload will be `length` bytes long, the payload is
+/// appended by the caller. Panics if `length` is more than [`MAX_PAYLOAD_LENGTH`].
+pub fn start(kind: MessageKind, algorithm: u8, length: usize) -> Vec<u8> {
+    assert!(length <= MAX_PAYLOAD_LENGTH, "payload too long");
+    let mut frame = Vec::with_capacity(HEADER_LENGTH + 2 + length + CHECKSUM_LENGTH);
+    frame.extend_from_slice(&header(kind, algorithm));
+    if length < LONG_LENGTH as usize {
+        frame.push(length as u8);
+    } else {
+        frame.extend_from_slice(&(length as u16 | 0x8000).to_be_bytes());
+    }
+    frame
+}
+
+/// Appends the checksum to a frame begun with [`start`] once its payload is complete
+pub fn finish(mut frame: Vec<u8>) -> Vec<u8> {
+    let crc = checksum(&frame);
+    frame.extend_from_slice(&crc.to_be_bytes());
+    frame
+}
+
+/// Wraps `payload` into a frame of `kind`, panics if it is longer than [`MAX_PAYLOAD_LENGTH`]
+pub fn encode(kind: MessageKind, algorithm: u8, payload: &[u8]) -> Vec<u8> {
+    let mut frame = start(kind, algorithm, payload.len());
+    frame.extend_from_slice(payload);
+    finish(frame)
+}
+
+/// Parses a complete frame, anything else than exactly one well-formed frame is refused
+pub fn decode(bytes: &[u8]) -> Result<Frame<'_>, ProtocolError> {
+    if bytes.len() < HEADER_LENGTH + 1 + CHECKSUM_LENGTH {
+        return Err(ProtocolError::Malformed);
+    }
+    if bytes[0] != PROTOCOL_VERSION {
+        return Err(ProtocolError::UnknownVersion(bytes[0]));
+    }
+    let (framed, crc) = bytes.split_at(bytes.len() - CHECKSUM_LENGTH);
+    if checksum(framed) != u16::from_be_bytes([crc[0], crc[1]]) {
+        return Err(ProtocolError::Checksum);
+    }
+    let kind = MessageKind::try_from(framed[1])?;
+    let algorithm = framed[2];
+
+    let rest = &framed[HEADER_LENGTH..];
+    let (length, payload) = if rest[0] & LONG_LENGTH == 0 {
+        (rest[0] as usize, &rest[1..])
+    } else {
+        if rest.len() < 2 {
+            return Err(ProtocolError::Malformed);
+        }
+        let length = u16::from_be_bytes([rest[0] & !LONG_LENGTH, rest[1]]) as usize;
+        if length < LONG_LENGTH as usize {
+            return Err(ProtocolError::Malformed);
+        }
+        (length, &rest[2..])
+    };
+    if payload.len() != length {
+        return Err(ProtocolError::Malformed);
+    }
+    Ok(Frame {
+        kind,
+        algorithm,
+        payload,
+        framed,
+    })
+}
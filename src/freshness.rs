@@ -0,0 +1,139 @@
+use crate::crypto::SHA256_LENGTH;
+use crate::{
+    constant_time_eq, nanos, shift, ProtocolError, FRESHNESS_WINDOW, MAX_FORWARD_SKEW,
+    MAX_LEARNED_SKEW,
+};
+use std::collections::VecDeque;
+use std::time::Duration;
+
+// Learned skew moves this fraction of the way towards every accepted observation,
+// enough to follow a drifting oscillator without chasing radio latency jitter
+const SKEW_LEARNING_RATE: i64 = 4;
+
+/// How old and how far ahead of the car's clock a frame may be
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub struct FreshnessPolicy {
+    pub max_age: Duration,          // oldest timestamp or challenge the car accepts
+    pub max_forward_skew: Duration, // how far a timestamp may be ahead of the car's clock
+    pub max_learned_skew: Duration, // largest keychain clock offset the car compensates, zero disables learning
+}
+
+impl Default for FreshnessPolicy {
+    fn default() -> FreshnessPolicy {
+        FreshnessPolicy {
+            max_age: FRESHNESS_WINDOW,
+            max_forward_skew: MAX_FORWARD_SKEW,
+            max_learned_skew: MAX_LEARNED_SKEW,
+        }
+    }
+}
+
+// Remembers recently accepted (timestamp, hash of the signed bytes) pairs so a captured
+// Command cannot be played back while it is still fresh
+struct ReplayCache {
+    capacity: usize,
+    accepted: VecDeque<(Duration, [u8; SHA256_LENGTH])>,
+    forgotten: Duration, // newest timestamp evicted, anything up to it is refused
+}
+
+impl ReplayCache {
+    fn new(capacity: usize) -> ReplayCache {
+        ReplayCache {
+            capacity,
+            accepted: VecDeque::with_capacity(capacity),
+            forgotten: Duration::default(),
+        }
+    }
+
+    // Records the pair, fails if the signed bytes were already seen or there is no room left.
+    // Entries are evicted only once they are older than `max_age` at `now`, so a full
+    // cache refuses new messages instead of forgetting ones that could still be replayed.
+    // Times are on the keychain's clock, and since the learned skew may shift later,
+    // evicted frames stay refused through `forgotten` rather than by their age alone.
+    fn insert(
+        &mut self,
+        time: Duration,
+        signed: [u8; SHA256_LENGTH],
+        now: Duration,
+        max_age: Duration,
+    ) -> Result<(), ProtocolError> {
+        while let Some((oldest, _)) = self.accepted.front() {
+            match now.checked_sub(*oldest) {
+                Some(duration) if duration >= max_age => {
+                    self.forgotten = self.forgotten.max(*oldest);
+                    self.accepted.pop_front();
+                }
+                _ => break,
+            }
+        }
+        // the signed bytes hold the timestamp, so their hash alone tells a replay, however the
+        // signature was reworked, and every entry is compared in full whatever its time
+        let seen = self
+            .accepted
+            .iter()
+            .fold(false, |seen, (_, s)| seen | constant_time_eq(s, &signed));
+        if time <= self.forgotten || seen {
+            return Err(ProtocolError::Replay);
+        }
+        if self.accepted.len() >= self.capacity {
+            return Err(ProtocolError::ReplayCacheFull);
+        }
+        self.accepted.push_back((time, signed));
+        Ok(())
+    }
+}
+
+// Checks keychain timestamps against the car's clock, compensating for the skew
+// learned from earlier authenticated frames
+pub(crate) struct Freshness {
+    pub(crate) policy: FreshnessPolicy,
+    pub(crate) observed_skew: Option<i64>, // of the last authenticated timestamp
+    pub(crate) learned_skew: i64,
+    replay: ReplayCache,
+}
+
+impl Freshness {
+    pub(crate) fn new(policy: FreshnessPolicy, replay_capacity: usize) -> Freshness {
+        Freshness {
+            policy,
+            observed_skew: None,
+            learned_skew: 0,
+            replay: ReplayCache::new(replay_capacity),
+        }
+    }
+
+    pub(crate) fn set_replay_capacity(&mut self, capacity: usize) {
+        self.replay = ReplayCache::new(capacity);
+    }
+
+    // Accepts `time` from a frame whose signed bytes hash to `signed` if it is fresh at `now`
+    // and not a replay. Must only be called for authenticated frames, they are what the skew
+    // is learned from.
+    pub(crate) fn check(
+        &mut self,
+        time: Duration,
+        signed: [u8; SHA256_LENGTH],
+        now: Duration,
+    ) -> Result<(), ProtocolError> {
+        let observed = nanos(time) - nanos(now);
+        self.observed_skew = Some(observed);
+        let offset = observed - self.learned_skew;
+        if offset > nanos(self.policy.max_forward_skew) {
+            return Err(ProtocolError::FromFuture);
+        }
+        if offset <= -nanos(self.policy.max_age) {
+            return Err(ProtocolError::Stale);
+        }
+        self.replay.insert(
+            time,
+            signed,
+            shift(now, self.learned_skew),
+            self.policy.max_age,
+        )?;
+
+        let limit = nanos(self.policy.max_learned_skew);
+        self.learned_skew += offset / SKEW_LEARNING_RATE;
+        self.learned_skew = self.learned_skew.clamp(-limit, limit);
+        Ok(())
+    }
+}
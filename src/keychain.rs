@@ -0,0 +1,350 @@
+use crate::clock::{Clock, MonotonicClock};
+use crate::crypto::{Algorithm, Backend, Verifier};
+use crate::frame::{self, NO_ALGORITHM};
+use crate::{
+    bit, hex, nanos, nonce, set_bit, shift, signed_frame, to_timestamp, CarState, Command,
+    DenyReason, Message, MessageKind, MessageProcessor, Mode, ProtocolError, RangeBit, Signature,
+    TimeSyncReply, NONCE_LENGTH, RANGE_MASK_LENGTH, RANGE_ROUNDS, RETRY_ATTEMPTS, RETRY_TIMEOUT,
+};
+use std::time::Duration;
+
+/// How often and how patiently a keychain presses a command the car has not confirmed
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub struct RetryPolicy {
+    pub attempts: u32,     // presses of one command at most, including the first
+    pub timeout: Duration, // how long to wait for the car's reply before pressing again
+}
+
+impl Default for RetryPolicy {
+    fn default() -> RetryPolicy {
+        RetryPolicy {
+            attempts: RETRY_ATTEMPTS,
+            timeout: RETRY_TIMEOUT,
+        }
+    }
+}
+
+// What the keychain answered of the car's distance bounding so far
+struct Ranging {
+    round: usize, // next round expected
+    mask: [u8; RANGE_MASK_LENGTH],
+    challenges: [u8; RANGE_MASK_LENGTH],
+}
+
+/// Holds the private key and sends commands to its paired car
+pub struct Keychain<B: Backend> {
+    signer: B::Signer,     // keychain's own key
+    verifier: B::Verifier, // car's public key, checks its replies
+    mode: Mode,
+    awaiting_challenge: Option<Command>, // command to sign once the car's Challenge arrives
+    counter: u64,                        // last counter sent, must survive power loss on a real fob
+    pending: Option<Vec<u8>>,            // command and token still waiting for a reply
+    confirmed: bool,
+    car_state: Option<CarState>,
+    clock: Box<dyn Clock>,
+    clock_offset: i64, // nanoseconds added to the clock, learned from the car's TimeSync
+    sync: Option<[u8; NONCE_LENGTH]>, // nonce of the TimeSyncRequest still waiting for a reply
+    retry: RetryPolicy,
+    pressing: Option<(Command, Duration)>, // unconfirmed command and when to press it again
+    attempts: u32,                         // presses of the last command so far
+    ranging: Option<Ranging>,
+}
+
+impl<B: Backend> Keychain<B> {
+    /// Creates a keychain signing with the PKCS#8 `private_pem` that trusts replies
+    /// from the car holding the private half of `car_pem`
+    pub fn new(
+        private_pem: &[u8],
+        car_pem: &[u8],
+        mode: Mode,
+        algorithm: Algorithm,
+    ) -> Result<Keychain<B>, ProtocolError> {
+        Ok(Keychain {
+            signer: B::signer(algorithm, private_pem)?,
+            verifier: B::verifier(algorithm, car_pem)?,
+            mode,
+            awaiting_challenge: None,
+            counter: 0,
+            pending: None,
+            confirmed: false,
+            car_state: None,
+            clock: Box::new(MonotonicClock::new()),
+            clock_offset: 0,
+            sync: None,
+            retry: RetryPolicy::default(),
+            pressing: None,
+            attempts: 0,
+            ranging: None,
+        })
+    }
+
+    /// Timestamps commands with `clock` instead of a [`MonotonicClock`]
+    pub fn with_clock(mut self, clock: Box<dyn Clock>) -> Keychain<B> {
+        self.clock = clock;
+        self
+    }
+
+    /// Continues counting from `counter` in counter mode, the last one sent as saved from
+    /// [`counter`](Keychain::counter) before the keychain lost power
+    pub fn with_counter(mut self, counter: u64) -> Keychain<B> {
+        self.counter = counter;
+        self
+    }
+
+    /// Replaces the default [`RetryPolicy`]
+    pub fn with_retry(mut self, retry: RetryPolicy) -> Keychain<B> {
+        self.retry = retry;
+        self
+    }
+
+    /// Nanoseconds the car's clock is ahead of ours, as of the last TimeSync
+    pub fn clock_offset(&self) -> i64 {
+        self.clock_offset
+    }
+
+    /// Returns a TimeSyncRequest asking the car for its time, send it when the car
+    /// denies commands as expired
+    pub fn sync_time(&mut self) -> Result<Vec<u8>, ProtocolError> {
+        let nonce = nonce::<B>();
+        self.sync = Some(nonce);
+        signed_frame(&self.signer, MessageKind::TimeSyncRequest, &nonce, &[])
+    }
+
+    /// Whether the car has confirmed the last command with a valid signed Success
+    pub fn confirmed(&self) -> bool {
+        self.confirmed
+    }
+
+    /// Whether the last command is unconfirmed and [`poll`](Keychain::poll) may still press it again
+    pub fn waiting(&self) -> bool {
+        self.pressing.is_some()
+    }
+
+    /// Last counter sent in counter mode, save it before sending a Command so no counter is
+    /// ever signed twice
+    pub fn counter(&self) -> u64 {
+        self.counter
+    }
+
+    /// How many times the last command was pressed
+    pub fn attempts(&self) -> u32 {
+        self.attempts
+    }
+
+    /// State the car reported in its last confirmed Success
+    pub fn car_state(&self) -> Option<CarState> {
+        self.car_state
+    }
+
+    // Builds a Command frame signed over the command and the freshness token
+    fn command_message(
+        &mut self,
+        command: Command,
+        token: &[u8],
+    ) -> Result<Vec<u8>, ProtocolError> {
+        let mut body = vec![command as u8];
+        body.extend_from_slice(token);
+        self.pending = Some(body.clone());
+        signed_frame(&self.signer, MessageKind::Command, &body, &[])
+    }
+
+    /// Returns the first frame of `command`: the Command itself, or a Hello in challenge mode
+    pub fn command(&mut self, command: Command) -> Result<Vec<u8>, ProtocolError> {
+        self.attempts = 0;
+        self.press(command)
+    }
+
+    /// Returns the first frame of the last command once more when the car did not confirm it
+    /// in time or refused it for a reason the radio may be to blame for, call it periodically
+    /// while [`waiting`](Keychain::waiting)
+    pub fn poll(&mut self) -> Result<Option<Vec<u8>>, ProtocolError> {
+        let (command, retry_at) = match self.pressing {
+            Some(pressing) => pressing,
+            None => return Ok(None),
+        };
+        if self.clock.now() < retry_at {
+            return Ok(None);
+        }
+        if self.attempts >= self.retry.attempts {
+            self.pressing = None;
+            return Ok(None);
+        }
+        self.press(command).map(Some)
+    }
+
+    // Starts another attempt at `command` with a fresh token
+    fn press(&mut self, command: Command) -> Result<Vec<u8>, ProtocolError> {
+        self.confirmed = false;
+        self.attempts += 1;
+        self.pressing = Some((command, self.clock.now() + self.retry.timeout));
+        match self.mode {
+            Mode::Timestamp => {
+                let now = shift(self.clock.now(), self.clock_offset);
+                self.command_message(command, &to_timestamp(now))
+            }
+            Mode::Counter => {
+                self.counter += 1;
+                self.command_message(command, &self.counter.to_be_bytes())
+            }
+            Mode::Challenge => {
+                // the token comes with the car's Challenge, until then no reply is for us
+                self.pending = None;
+                self.awaiting_challenge = Some(command);
+                Ok(frame::encode(MessageKind::Hello, NO_ALGORITHM, &[]))
+            }
+        }
+    }
+
+    /// Returns the first frame of an unlock
+    pub fn get_initiation_message(&mut self) -> Result<Vec<u8>, ProtocolError> {
+        self.command(Command::Open)
+    }
+}
+
+impl<B: Backend> Keychain<B> {
+    // Checks that a reply is signed by our car over the frame and `bound`
+    fn verify_signed(&self, signature: &Signature, bound: &[u8]) -> Result<(), ProtocolError> {
+        if signature.algorithm != self.verifier.algorithm() as u8 {
+            return Err(ProtocolError::WrongAlgorithm);
+        }
+        let signed = [signature.covers, bound].concat();
+        if !self.verifier.verify(&signed, signature.bytes) {
+            return Err(ProtocolError::BadSignature);
+        }
+        Ok(())
+    }
+
+    // Checks that a reply answers the pending command, returns whether it does. Consumes
+    // the pending command, so every command is answered once; replies while nothing is
+    // pending are not for us.
+    fn verify_reply(&mut self, signature: &Signature) -> Result<bool, ProtocolError> {
+        match &self.pending {
+            Some(pending) => self.verify_signed(signature, pending)?,
+            None => return Ok(false),
+        }
+        self.pending = None;
+        Ok(true)
+    }
+
+    // Settles the command the car answered: a Success or a refusal that fits the car's state
+    // ends it, other refusals may stem from a lost or duplicated frame and are retried at once
+    fn settle(&mut self, retry: bool) {
+        self.pressing = match self.pressing {
+            Some((command, _)) if retry => Some((command, self.clock.now())),
+            _ => None,
+        };
+    }
+
+    // Answers a round of the car's distance bounding for the pending command at once,
+    // and signs for all of them once the car asks for the proof
+    fn process_range_challenge(
+        &mut self,
+        challenge: &RangeBit,
+    ) -> Result<Option<Vec<u8>>, ProtocolError> {
+        let pending = match &self.pending {
+            Some(pending) => pending,
+            None => return Ok(None),
+        };
+        let round = challenge.round as usize;
+        if round == 0 {
+            let mut mask = [0u8; RANGE_MASK_LENGTH];
+            B::random(&mut mask);
+            self.ranging = Some(Ranging {
+                round: 0,
+                mask,
+                challenges: [0u8; RANGE_MASK_LENGTH],
+            });
+        }
+        let ranging = match &mut self.ranging {
+            Some(ranging) if ranging.round == round => ranging,
+            _ => return Ok(None),
+        };
+        if round < RANGE_ROUNDS {
+            set_bit(&mut ranging.challenges, round, challenge.bit);
+            ranging.round += 1;
+            let response = challenge.bit ^ bit(&ranging.mask, round);
+            return Ok(Some(frame::encode(
+                MessageKind::RangeResponse,
+                NO_ALGORITHM,
+                &[round as u8, response as u8],
+            )));
+        }
+        let mut responses = ranging.challenges;
+        for (response, mask) in responses.iter_mut().zip(ranging.mask.iter()) {
+            *response ^= mask;
+        }
+        let bound = [&pending[..], &ranging.challenges, &responses].concat();
+        let proof = signed_frame(&self.signer, MessageKind::RangeProof, &ranging.mask, &bound);
+        self.ranging = None;
+        proof.map(Some)
+    }
+
+    // Adopts the car's time from a TimeSync answering our pending request
+    fn process_time_sync(
+        &mut self,
+        reply: &TimeSyncReply,
+    ) -> Result<Option<Vec<u8>>, ProtocolError> {
+        let nonce = match self.sync {
+            Some(nonce) => nonce,
+            None => return Ok(None),
+        };
+        self.verify_signed(&reply.signature, &nonce)?;
+        self.sync = None;
+        self.clock_offset = nanos(reply.time) - nanos(self.clock.now());
+        log::debug!(
+            "keys recieved TimeSync, clock offset: {} ns",
+            self.clock_offset
+        );
+        Ok(None)
+    }
+
+    /// Acts on a parsed frame, returns the frame to transmit in reply
+    pub fn handle(&mut self, message: &Message) -> Result<Option<Vec<u8>>, ProtocolError> {
+        match message {
+            Message::Challenge(challenge) => {
+                let command = match self.awaiting_challenge {
+                    Some(command) => command,
+                    None => return Ok(None),
+                };
+                self.verify_signed(&challenge.signature, &[])?;
+                log::debug!("keys recieved Challenge:\n{}", hex(challenge.nonce));
+                self.awaiting_challenge = None;
+                self.command_message(command, challenge.nonce).map(Some)
+            }
+            Message::Success(reply) => {
+                if !self.verify_reply(&reply.signature)? {
+                    return Ok(None);
+                }
+                log::debug!("keys recieved Success, car state: {:?}", reply.state);
+                self.car_state = Some(reply.state);
+                self.confirmed = true;
+                self.settle(false);
+                Ok(None)
+            }
+            Message::Denied(reply) => match self.verify_reply(&reply.signature)? {
+                true => {
+                    self.settle(reply.reason != DenyReason::Unavailable);
+                    Err(ProtocolError::Denied(reply.reason))
+                }
+                false => Ok(None),
+            },
+            Message::ResyncRequired(reply) => match self.verify_reply(&reply.signature)? {
+                true => {
+                    self.settle(true);
+                    Err(ProtocolError::ResyncRequired)
+                }
+                false => Ok(None),
+            },
+            Message::TimeSync(reply) => self.process_time_sync(reply),
+            Message::RangeChallenge(challenge) => self.process_range_challenge(challenge),
+            _ => Ok(None),
+        }
+    }
+}
+
+impl<B: Backend> MessageProcessor for Keychain<B> {
+    fn process(&mut self, message: &[u8]) -> Result<Option<Vec<u8>>, ProtocolError> {
+        let message = Message::parse(message, self.mode)?;
+        self.handle(&message)
+    }
+}
@@ -0,0 +1,310 @@
+//! Keychain protocol: a keychain proves to its paired car that it is allowed to command it.
+//!
+//! Devices exchange frames over a shared medium (the "ether"), every device implements
+//! [`MessageProcessor`] and may answer any frame with a frame of its own. Each device reaches
+//! the medium through a [`transport::Transport`], the in-memory [`transport::Ether`] or
+//! anything else that carries frames.
+//!
+//! # Wire format
+//!
+//! Every message travels in a [`frame`] carrying the protocol version, the [`MessageKind`],
+//! the [`crypto::Algorithm`] of its signature, the payload length and a checksum. Integers
+//! are big-endian. Payloads by kind:
+//!
+//! | kind              | payload                                  |
+//! |-------------------|------------------------------------------|
+//! | `Hello`           | empty                                    |
+//! | `Challenge`       | `[nonce: 8][signature]`                  |
+//! | `Command`         | `[command: 1][token][signature]`         |
+//! | `Success`         | `[state: 1][signature]`                  |
+//! | `Denied`          | `[reason: 1][signature]`                 |
+//! | `ResyncRequired`  | `[signature]`                            |
+//! | `TimeSyncRequest` | `[nonce: 8][signature]`                  |
+//! | `TimeSync`        | `[time: 8][signature]`                   |
+//! | `RangeChallenge`  | `[round: 1][bit: 1]`                     |
+//! | `RangeResponse`   | `[round: 1][bit: 1]`                     |
+//! | `RangeProof`      | `[mask: 2][signature]`                   |
+//!
+//! The [`Command`] byte says what the car should do, `Success` reports the resulting
+//! [`CarState`]. The `Command` token depends on the pair's [`Mode`]: 8 bytes of nanoseconds
+//! since the Unix epoch, the 8-byte nonce of the last `Challenge` or an 8-byte counter.
+//! Signatures cover every frame byte before them, their length is whatever the algorithm
+//! produces (64 bytes for Ed25519 and ECDSA P-256, which keeps every frame under 80 bytes).
+//!
+//! A car that refuses a `Command` answers with `Denied` and a [`DenyReason`], or with
+//! `ResyncRequired` when a counter jumped too far ahead and the keychain should press again.
+//! Frames the car cannot attribute to its keychain are not answered at all.
+//!
+//! Both sides have their own key pair of the same algorithm. The car signs its replies with
+//! its key over the command and token of the `Command` it answers as well. The keychain
+//! knows both, so they are not sent back, and a reply can neither be forged nor played back
+//! for another command. The car signs its `Challenge`s too, so a keychain near several cars
+//! answers its own.
+//!
+//! A car that bounds the distance to its keychain does not act on a `Command` right away. It
+//! sends [`RANGE_ROUNDS`] `RangeChallenge`s with a random bit each, one after the other, and
+//! times how long each takes to come back. The keychain answers every one at once with a
+//! `RangeResponse`, the bit XORed with its own random mask, and finally with a `RangeProof`
+//! revealing the mask, signed over the command, the token and every bit sent either way as
+//! well. A relay adds its latency to every round, and without the mask it cannot answer early.
+//!
+//! In timestamp mode a keychain whose clock drifted asks its car for the time with a
+//! `TimeSyncRequest`. The car's `TimeSync` signature also covers the request's nonce,
+//! so only an answer to the keychain's latest request is accepted.
+//!
+//! Radios that carry fewer bytes per packet than a frame has wrap their transport in a
+//! [`fragment::Fragmented`] one, which splits frames into packets and puts them back together.
+//!
+//! A keychain presses a command again when its car does not confirm it in time, as its
+//! [`RetryPolicy`] allows. How well that works over a radio that loses, repeats and damages
+//! frames can be measured with a [`simulation::Simulation`].
+//!
+//! The [`attack`] module has the attackers the protocol is tested against.
+//!
+//! Received frames are parsed into a [`Message`] whose fields borrow from the frame, cars and
+//! keychains act on those.
+//!
+//! The car moves between the [`LockState`]s on authenticated commands, door reports and its
+//! auto-relock timer, and hands every change to its listeners as an [`Event`].
+
+pub mod attack;
+pub mod clock;
+pub mod crypto;
+pub mod fragment;
+pub mod frame;
+pub mod simulation;
+pub mod transport;
+
+mod car;
+mod command;
+mod error;
+mod freshness;
+mod keychain;
+mod message;
+mod state;
+
+pub use car::{Car, Diagnostics};
+pub use command::Command;
+pub use error::ProtocolError;
+pub use freshness::FreshnessPolicy;
+pub use keychain::{Keychain, RetryPolicy};
+pub use message::{
+    ChallengeReply, CommandRequest, DeniedReply, Message, RangeBit, RangeProof, ResyncReply,
+    Signature, SuccessReply, TimeSyncReply, TimeSyncRequest,
+};
+pub use state::{CarState, Event, Listener, LockState, Trigger};
+
+use crypto::{Algorithm, Backend, KeyError, Signer};
+use std::collections::VecDeque;
+use std::convert::TryFrom;
+use std::time::Duration;
+use subtle::ConstantTimeEq;
+use transport::{Address, Ether, Transport};
+
+pub const TIME_LENGTH: usize = 8;
+pub const NONCE_LENGTH: usize = 8;
+pub const COUNTER_LENGTH: usize = 8;
+pub const ALGORITHM_LENGTH: usize = 1;
+pub const COMMAND_LENGTH: usize = 1;
+pub const FRESHNESS_WINDOW: Duration = Duration::from_secs(1);
+pub const MAX_FORWARD_SKEW: Duration = Duration::from_millis(250);
+pub const MAX_LEARNED_SKEW: Duration = Duration::from_secs(5);
+pub const REPLAY_CACHE_CAPACITY: usize = 64;
+pub const COUNTER_LOOK_AHEAD: u64 = 16;
+pub const AUTO_RELOCK: Duration = Duration::from_secs(30);
+pub const RETRY_ATTEMPTS: u32 = 3;
+pub const RETRY_TIMEOUT: Duration = Duration::from_millis(250);
+/// Rapid rounds of distance bounding, one challenge bit each
+pub const RANGE_ROUNDS: usize = 16;
+pub const RANGE_MASK_LENGTH: usize = RANGE_ROUNDS / 8;
+
+pub fn hex(bytes: &[u8]) -> String {
+    bytes
+        .iter()
+        .map(|b| format!("{:02x}", b))
+        .collect::<String>()
+}
+
+fn to_timestamp(time: Duration) -> [u8; TIME_LENGTH] {
+    (time.as_nanos() as u64).to_be_bytes()
+}
+
+fn from_timestamp(bytes: &[u8]) -> Duration {
+    let mut nanos = [0u8; TIME_LENGTH];
+    nanos.copy_from_slice(bytes);
+    Duration::from_nanos(u64::from_be_bytes(nanos))
+}
+
+fn nanos(duration: Duration) -> i64 {
+    duration.as_nanos() as i64
+}
+
+// Moves `time` by a signed number of nanoseconds
+fn shift(time: Duration, by: i64) -> Duration {
+    if by >= 0 {
+        time + Duration::from_nanos(by as u64)
+    } else {
+        time.checked_sub(Duration::from_nanos(by.unsigned_abs()))
+            .unwrap_or_default()
+    }
+}
+
+// Bit `index` of `bits`, counting from the least significant bit of the first byte
+fn bit(bits: &[u8], index: usize) -> bool {
+    bits[index / 8] >> (index % 8) & 1 == 1
+}
+
+fn set_bit(bits: &mut [u8], index: usize, value: bool) {
+    bits[index / 8] &= !(1 << (index % 8));
+    bits[index / 8] |= (value as u8) << (index % 8);
+}
+
+// Whether `a` and `b` are equal, in a time that depends on their lengths alone, for anything
+// an attacker could learn about byte by byte from how soon a comparison gives up
+fn constant_time_eq(a: &[u8], b: &[u8]) -> bool {
+    a.ct_eq(b).into()
+}
+
+fn nonce<B: Backend>() -> [u8; NONCE_LENGTH] {
+    let mut nonce = [0u8; NONCE_LENGTH];
+    B::random(&mut nonce);
+    nonce
+}
+
+// Builds a frame of `kind` carrying `body` and a signature over every byte before it followed
+// by `bound`, which the receiver already knows and is therefore not transmitted
+fn signed_frame<S: Signer>(
+    signer: &S,
+    kind: MessageKind,
+    body: &[u8],
+    bound: &[u8],
+) -> Result<Vec<u8>, ProtocolError> {
+    let length = signer.signature_length();
+    let mut frame = frame::start(kind, signer.algorithm() as u8, body.len() + length);
+    frame.extend_from_slice(body);
+    let signed = frame.len();
+    frame.extend_from_slice(bound);
+    let signature = signer.sign(&frame)?;
+    if signature.len() != length {
+        return Err(KeyError.into());
+    }
+    frame.truncate(signed);
+    frame.extend_from_slice(&signature);
+    Ok(frame::finish(frame))
+}
+
+/// How a car and its keychain prove that a Command is fresh
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum Mode {
+    Timestamp, // keychain signs its current time
+    Challenge, // keychain signs a random nonce issued by the car
+    Counter,   // keychain signs a monotonically increasing counter
+}
+
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum MessageKind {
+    Command = 1,              // keychain sends this with a Command for the car to carry out
+    TimeSync = 1 << 1,        // car sends this with its time in answer to a TimeSyncRequest
+    Success = 1 << 2,         // car sends this with its new CarState after carrying out a Command
+    Hello = 1 << 3,           // keychain sends this to ask the car for a challenge
+    Challenge = 1 << 4,       // car sends this with a fresh nonce for the keychain to sign
+    Denied = 1 << 5,          // car sends this with a DenyReason when it refuses a Command
+    ResyncRequired = 1 << 6,  // car sends this when it needs one more counter to resynchronize
+    TimeSyncRequest = 1 << 7, // keychain sends this with a fresh nonce to ask for the car's time
+    RangeChallenge = 0x03,    // car sends this with the next bit of distance bounding
+    RangeResponse = 0x05,     // keychain sends this at once in answer to a RangeChallenge
+    RangeProof = 0x06,        // keychain sends this with its mask once every round is answered
+}
+
+/// Reason code of a Denied reply, deliberately coarse so it tells an attacker nothing new
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum DenyReason {
+    Rejected = 1,    // replayed, not permitted or otherwise unacceptable, no further detail
+    Expired = 2,     // too old or too far ahead of the car's clock, press again
+    Busy = 3,        // car refuses everything for a while, try again later
+    Unavailable = 4, // command does not fit the car's state, e.g. starting a running engine
+}
+
+/// A device listening to the ether
+pub trait MessageProcessor {
+    /// Handles a frame heard in the ether, returns the frame to transmit in reply.
+    /// Frames meant for other devices are ignored with `Ok(None)`, an error means
+    /// the frame was addressed to this device but refused.
+    fn process(&mut self, message: &[u8]) -> Result<Option<Vec<u8>>, ProtocolError>;
+
+    /// Returns the frame to transmit after `process` refused a frame with `error`
+    fn refusal(&mut self, _error: ProtocolError) -> Option<Vec<u8>> {
+        None
+    }
+}
+
+impl TryFrom<u8> for MessageKind {
+    type Error = ProtocolError;
+
+    fn try_from(value: u8) -> Result<Self, Self::Error> {
+        match value {
+            x if x == MessageKind::Command as u8 => Ok(MessageKind::Command),
+            x if x == MessageKind::TimeSync as u8 => Ok(MessageKind::TimeSync),
+            x if x == MessageKind::Success as u8 => Ok(MessageKind::Success),
+            x if x == MessageKind::Hello as u8 => Ok(MessageKind::Hello),
+            x if x == MessageKind::Challenge as u8 => Ok(MessageKind::Challenge),
+            x if x == MessageKind::Denied as u8 => Ok(MessageKind::Denied),
+            x if x == MessageKind::ResyncRequired as u8 => Ok(MessageKind::ResyncRequired),
+            x if x == MessageKind::TimeSyncRequest as u8 => Ok(MessageKind::TimeSyncRequest),
+            x if x == MessageKind::RangeChallenge as u8 => Ok(MessageKind::RangeChallenge),
+            x if x == MessageKind::RangeResponse as u8 => Ok(MessageKind::RangeResponse),
+            x if x == MessageKind::RangeProof as u8 => Ok(MessageKind::RangeProof),
+            _ => Err(ProtocolError::UnknownKind),
+        }
+    }
+}
+
+impl TryFrom<u8> for DenyReason {
+    type Error = ProtocolError;
+
+    fn try_from(value: u8) -> Result<Self, Self::Error> {
+        match value {
+            x if x == DenyReason::Rejected as u8 => Ok(DenyReason::Rejected),
+            x if x == DenyReason::Expired as u8 => Ok(DenyReason::Expired),
+            x if x == DenyReason::Busy as u8 => Ok(DenyReason::Busy),
+            x if x == DenyReason::Unavailable as u8 => Ok(DenyReason::Unavailable),
+            _ => Err(ProtocolError::Malformed),
+        }
+    }
+}
+
+/// Generates key pairs for a new car and keychain and lets them exchange public keys
+pub fn make_key_car_pair<B: Backend>(
+    mode: Mode,
+    algorithm: Algorithm,
+) -> Result<(Car<B>, Keychain<B>), ProtocolError> {
+    let (car_public, car_private) = B::generate(algorithm)?;
+    let (keychain_public, keychain_private) = B::generate(algorithm)?;
+    Ok((
+        Car::new(&car_private, &keychain_public, mode, algorithm)?,
+        Keychain::new(&keychain_private, &car_public, mode, algorithm)?,
+    ))
+}
+
+/// Broadcasts `frames` from the back to every device over an [`Ether`] and lets the devices
+/// answer until it falls silent, returns all the frames that were transmitted in order.
+/// Refusals are logged.
+pub fn run(
+    devices: &mut [&mut dyn MessageProcessor],
+    mut frames: VecDeque<Vec<u8>>,
+) -> Vec<Vec<u8>> {
+    let ether = Ether::new();
+    let mut transports: Vec<_> = devices.iter().map(|_| ether.connect()).collect();
+    let mut outsider = ether.connect();
+    while let Some(frame) = frames.pop_back() {
+        outsider
+            .send(Address::BROADCAST, &frame)
+            .expect("the ether does not fail");
+    }
+    let mut stations: Vec<(&mut dyn MessageProcessor, &mut dyn Transport)> = Vec::new();
+    for (device, transport) in devices.iter_mut().zip(transports.iter_mut()) {
+        stations.push((&mut **device, transport));
+    }
+    transport::serve_all(&mut stations).expect("the ether does not fail");
+    ether.log()
+}
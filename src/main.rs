@@ -1,173 +1,189 @@
-extern crate openssl;
-extern crate sha2;
+extern crate keychain_protocol;
 
-use openssl::pkey::{Private, Public};
-use openssl::rsa::{Padding, Rsa};
-use sha2::{Digest, Sha256};
-use std::collections::VecDeque;
-use std::convert::TryFrom;
-use std::time::{Duration, SystemTime};
+use keychain_protocol::crypto::{Algorithm, Backend};
+use keychain_protocol::fragment::{Fragmented, RADIO_MTU};
+use keychain_protocol::simulation::Simulation;
+use keychain_protocol::transport::{serve_all, Address, Channel, Ether, Latency, Transport};
+use keychain_protocol::{hex, make_key_car_pair, Car, Command, Event, Keychain, Mode, RetryPolicy};
+use std::time::Duration;
 
-const TIME_LENGTH: usize = 8;
+// Prints what the devices log, every frame they handle
+struct Printer;
 
-fn hex(bytes: &[u8]) -> String {
-    bytes
-        .iter()
-        .map(|b| format!("{:02x}", b))
-        .collect::<String>()
-}
-
-fn now() -> [u8; TIME_LENGTH] {
-    let now = SystemTime::now()
-        .duration_since(SystemTime::UNIX_EPOCH)
-        .unwrap();
-    let nanos: u64 = now.as_nanos() as u64;
-    nanos.to_be_bytes()
-}
-
-fn elapsed(since: [u8; TIME_LENGTH], to: [u8; TIME_LENGTH]) -> Option<Duration> {
-    if let Some(diff) = u64::from_be_bytes(to).checked_sub(u64::from_be_bytes(since)) {
-        Some(Duration::from_nanos(diff))
-    } else {
-        None
-    }
-}
-
-struct Car {
-    rsa: Rsa<Public>,
-}
-
-struct Keychain {
-    rsa: Rsa<Private>,
-}
-
-enum MessageKind {
-    CommandOpen = 1, // keychain sends this to open the car
-    Success = 1 << 2, // car sends this to notify keychain about success of the operation
-}
-
-trait MessageProcessor {
-    fn process(self: &Self, message: &Vec<u8>) -> Option<Vec<u8>>;
-}
-
-impl TryFrom<u8> for MessageKind {
-    type Error = ();
-
-    fn try_from(value: u8) -> Result<Self, Self::Error> {
-        match value {
-            x if x == MessageKind::CommandOpen as u8 => Ok(MessageKind::CommandOpen),
-            x if x == MessageKind::Success as u8 => Ok(MessageKind::Success),
-            _ => Err(()),
-        }
-    }
-}
-
-impl Car {
-    fn new(pem: Vec<u8>) -> Car {
-        let rsa = Rsa::public_key_from_pem(&pem).unwrap();
-        Car { rsa }
+impl log::Log for Printer {
+    fn enabled(&self, _: &log::Metadata) -> bool {
+        true
     }
-}
 
-impl Keychain {
-    fn new(pem: Vec<u8>) -> Keychain {
-        let rsa = Rsa::private_key_from_pem(&pem).unwrap();
-        Keychain { rsa }
+    fn log(&self, record: &log::Record) {
+        println!("{}", record.args());
     }
 
-    fn get_initiation_message(self: &Self) -> Vec<u8> {
-        let mut message = vec![MessageKind::CommandOpen as u8];
-        let mut sha = Sha256::new();
-        let mut time = now().to_vec();
-        sha.input(&time);
-        let hash = sha.result();
-        let mut sign: Vec<u8> = vec![0; 256];
-        self.rsa
-            .private_encrypt(&hash, sign.as_mut_slice(), Padding::PKCS1)
-            .unwrap();
-        time.extend_from_slice(&sign);
-        message.extend_from_slice(&time);
-        message
-    }
+    fn flush(&self) {}
 }
 
-impl MessageProcessor for Car {
-    fn process(self: &Self, message: &Vec<u8>) -> Option<Vec<u8>> {
-        if message.len() > TIME_LENGTH + 1 {
-            if let Ok(MessageKind::CommandOpen) = MessageKind::try_from(message[0]) {
-                println!("car recieved CommandOpen:\n{}", hex(&message[..]));
-                let message = message[1..].to_vec();
-                let mut sha = Sha256::new();
-                let mut time = [0u8; TIME_LENGTH];
-                for i in 0..TIME_LENGTH {
-                    time[i] = message[i];
-                }
-
-                if let Some(duration) = elapsed(time, now()) {
-                    if duration.as_secs() < 1 {
-                        let mut decrypted_hash: Vec<u8> = vec![0; 256];
-                        if let Ok(_) = self.rsa.public_decrypt(
-                            &message[TIME_LENGTH..],
-                            decrypted_hash.as_mut_slice(),
-                            Padding::PKCS1,
-                        ) {
-                            sha.input(&time);
-                            let hash2 = sha.result().to_vec();
-                            for (k, &v) in hash2.iter().enumerate() {
-                                if decrypted_hash[k] != v {
-                                    return None;
-                                }
-                            }
-                            return Some(vec![MessageKind::Success as u8]);
-                        }
-                    }
-                }
-            }
+fn demo<B: Backend>() {
+    let pairs = [
+        (Mode::Timestamp, Algorithm::RsaPss),
+        (Mode::Challenge, Algorithm::RsaPss),
+        (Mode::Counter, Algorithm::RsaPss),
+        (Mode::Timestamp, Algorithm::RsaPkcs1v15),
+        (Mode::Challenge, Algorithm::Ed25519),
+        (Mode::Counter, Algorithm::EcdsaP256),
+    ];
+    for &(mode, algorithm) in &pairs {
+        println!("mode: {:?}, algorithm: {:?}", mode, algorithm);
+        let (car_public, car_private) = B::generate(algorithm).unwrap();
+        let (keychain_public, keychain_private) = B::generate(algorithm).unwrap();
+        println!(
+            "registration:\n\tcar:\n{}\n\tkeychain:\n{}",
+            hex(&car_public[..]),
+            hex(&keychain_public[..])
+        );
+        let mut car = Car::<B>::new(&car_private, &keychain_public, mode, algorithm).unwrap();
+        let mut keychain =
+            Keychain::<B>::new(&keychain_private, &car_public, mode, algorithm).unwrap();
+
+        let ether = Ether::new();
+        let (mut car_radio, mut keychain_radio) = (ether.connect(), ether.connect());
+        let message = keychain.get_initiation_message().unwrap();
+        keychain_radio.send(Address::BROADCAST, &message).unwrap();
+        if mode == Mode::Timestamp {
+            // an eavesdropper plays the captured frame back straight away
+            ether.connect().send(Address::BROADCAST, &message).unwrap();
+            // then the keychain checks its clock against the car's
+            let sync = keychain.sync_time().unwrap();
+            keychain_radio.send(Address::BROADCAST, &sync).unwrap();
         }
-        return None;
+        serve_all(&mut [
+            (&mut car, &mut car_radio),
+            (&mut keychain, &mut keychain_radio),
+        ])
+        .unwrap();
     }
-}
 
-impl MessageProcessor for Keychain {
-    fn process(self: &Self, message: &Vec<u8>) -> Option<Vec<u8>> {
-        if message.len() < 1 {
-            return None;
-        }
-        if let Ok(MessageKind::Success) = MessageKind::try_from(message[0]) {
-            println!("keys recieved Success");
+    println!("every command, mode: Challenge, algorithm: Ed25519");
+    let (car, mut keychain) = make_key_car_pair::<B>(Mode::Challenge, Algorithm::Ed25519).unwrap();
+    let mut car = car.with_listener(Box::new(|event: &Event| println!("car event: {:?}", event)));
+    let commands = [
+        Command::Open,
+        Command::WindowClose,
+        Command::Lock,
+        Command::EngineStart,
+        Command::EngineStart, // the engine is already running, the car denies this one
+        Command::EngineStop,
+        Command::TrunkRelease,
+        Command::Locate,
+        Command::Panic,
+        Command::Panic,
+    ];
+    let ether = Ether::new();
+    let (mut car_radio, mut keychain_radio) = (ether.connect(), ether.connect());
+    for &command in &commands {
+        println!("command: {:?}", command);
+        let message = keychain.command(command).unwrap();
+        keychain_radio.send(Address::BROADCAST, &message).unwrap();
+        serve_all(&mut [
+            (&mut car, &mut car_radio),
+            (&mut keychain, &mut keychain_radio),
+        ])
+        .unwrap();
+        if command == Command::Open {
+            println!("door opened");
+            car.door_opened();
         }
-        return None;
     }
-}
 
-fn make_key_car_pair() -> (Car, Keychain) {
-    let rsa = Rsa::generate(2048).unwrap();
-    let (public_pem, private_pem) = (
-        rsa.public_key_to_pem().unwrap(),
-        rsa.private_key_to_pem().unwrap(),
+    println!(
+        "radio packets of {} bytes, mode: Counter, algorithm: RsaPss",
+        RADIO_MTU
     );
+    let (mut car, mut keychain) = make_key_car_pair::<B>(Mode::Counter, Algorithm::RsaPss).unwrap();
+    let ether = Ether::new();
+    let mut car_radio = Fragmented::new(ether.connect(), RADIO_MTU, 0x1000);
+    let mut keychain_radio = Fragmented::new(ether.connect(), RADIO_MTU, 0x2000);
+    let message = keychain.command(Command::Open).unwrap();
+    keychain_radio.send(Address::BROADCAST, &message).unwrap();
+    serve_all(&mut [
+        (&mut car, &mut car_radio),
+        (&mut keychain, &mut keychain_radio),
+    ])
+    .unwrap();
     println!(
-        "registration:\n\tcar:\n{}\n\tkeychain:\n{}",
-        hex(&public_pem[..]),
-        hex(&private_pem[..])
+        "{} packets for a Command and its Success",
+        ether.log().len()
     );
-    (Car::new(public_pem), Keychain::new(private_pem))
 }
 
-fn main() {
-    let (car, keychain) = make_key_car_pair();
-    let devices: Vec<&dyn MessageProcessor> = vec![&car, &keychain];
-    let mut ether: VecDeque<Vec<u8>> = VecDeque::new();
-    let message = keychain.get_initiation_message();
-    ether.push_front(message);
-
-    while ether.len() > 0 {
-        if let Some(x) = ether.pop_back() {
-            for d in &devices {
-                if let Some(response) = d.process(&x) {
-                    ether.push_front(response);
-                }
+// Unlocks over ever worse radio channels with and without retries, the reports are printed
+// last since the devices log every frame
+fn simulate<B: Backend>() {
+    let radio = Channel {
+        latency: Latency::Exponential {
+            min: Duration::from_millis(2),
+            mean: Duration::from_millis(5),
+        },
+        ..Channel::default()
+    };
+    let channels = [
+        ("perfect", Channel::default()),
+        ("clean radio", radio),
+        (
+            "noisy radio",
+            Channel {
+                loss: 0.1,
+                duplication: 0.05,
+                reordering: 0.05,
+                bit_error_rate: 1e-4,
+                ..radio
+            },
+        ),
+        (
+            "edge of range",
+            Channel {
+                loss: 0.3,
+                duplication: 0.1,
+                reordering: 0.1,
+                bit_error_rate: 1e-3,
+                ..radio
+            },
+        ),
+    ];
+    let once = RetryPolicy {
+        attempts: 1,
+        ..RetryPolicy::default()
+    };
+    let mut reports = Vec::new();
+    for &(name, channel) in &channels {
+        for &mode in &[Mode::Timestamp, Mode::Challenge, Mode::Counter] {
+            for &(retry, retries) in &[(once, "no retries"), (RetryPolicy::default(), "retries")] {
+                let report = Simulation::new(mode, Algorithm::Ed25519, channel)
+                    .with_retry(retry)
+                    .with_seed(2020)
+                    .run::<B>(100)
+                    .unwrap();
+                reports.push(format!("{}, {:?}, {}: {}", name, mode, retries, report));
             }
         }
     }
-}
\ No newline at end of file
+    for report in reports {
+        println!("{}", report);
+    }
+}
+
+fn main() {
+    log::set_logger(&Printer).unwrap();
+    log::set_max_level(log::LevelFilter::Debug);
+    #[cfg(feature = "openssl")]
+    {
+        println!("backend: openssl");
+        demo::<keychain_protocol::crypto::OpenSsl>();
+        simulate::<keychain_protocol::crypto::OpenSsl>();
+    }
+    #[cfg(feature = "rustcrypto")]
+    {
+        println!("backend: rustcrypto");
+        demo::<keychain_protocol::crypto::RustCrypto>();
+        simulate::<keychain_protocol::crypto::RustCrypto>();
+    }
+}
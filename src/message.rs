@@ -0,0 +1,233 @@
+//! Typed views of the payloads in the crate docs' table.
+//!
+//! [`Message::parse`] checks a frame and the layout of its payload and hands out fields that
+//! borrow from the received bytes, nothing is copied. Signatures are only checked by the
+//! device the message is meant for.
+
+use crate::frame::{self, Frame};
+use crate::{
+    from_timestamp, CarState, Command, DenyReason, MessageKind, Mode, ProtocolError,
+    COMMAND_LENGTH, COUNTER_LENGTH, NONCE_LENGTH, RANGE_MASK_LENGTH, RANGE_ROUNDS, TIME_LENGTH,
+};
+use std::convert::TryFrom;
+use std::time::Duration;
+
+/// A signature and the frame bytes it covers
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub struct Signature<'a> {
+    pub algorithm: u8, // as claimed by the frame, checked against the pair's algorithm
+    pub covers: &'a [u8], // every frame byte before the signature
+    pub bytes: &'a [u8],
+}
+
+/// Car's answer to a Hello
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub struct ChallengeReply<'a> {
+    pub nonce: &'a [u8; NONCE_LENGTH],
+    pub signature: Signature<'a>,
+}
+
+/// Keychain's signed command
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub struct CommandRequest<'a> {
+    pub command: Command,
+    pub token: &'a [u8], // timestamp, challenge nonce or counter, depending on the mode
+    pub signature: Signature<'a>,
+}
+
+/// Car carried out the command it answers
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub struct SuccessReply<'a> {
+    pub state: CarState,
+    pub signature: Signature<'a>,
+}
+
+/// Car refused the command it answers
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub struct DeniedReply<'a> {
+    pub reason: DenyReason,
+    pub signature: Signature<'a>,
+}
+
+/// Car needs another command before it accepts the keychain's counter
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub struct ResyncReply<'a> {
+    pub signature: Signature<'a>,
+}
+
+/// Keychain asks for the car's time
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub struct TimeSyncRequest<'a> {
+    pub nonce: &'a [u8; NONCE_LENGTH],
+    pub signature: Signature<'a>,
+}
+
+/// Car's time, answering a TimeSyncRequest
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub struct TimeSyncReply<'a> {
+    pub time: Duration,
+    pub signature: Signature<'a>,
+}
+
+/// One rapid round of distance bounding, a RangeChallenge or a RangeResponse. The car asks
+/// for the proof with a RangeChallenge of round [`RANGE_ROUNDS`].
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub struct RangeBit {
+    pub round: u8,
+    pub bit: bool,
+}
+
+/// Keychain's mask, signed over the command it proves its distance for and every bit of the
+/// rounds
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub struct RangeProof<'a> {
+    pub mask: &'a [u8; RANGE_MASK_LENGTH],
+    pub signature: Signature<'a>,
+}
+
+/// A parsed frame, one variant per [`MessageKind`]
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum Message<'a> {
+    Hello,
+    Challenge(ChallengeReply<'a>),
+    Command(CommandRequest<'a>),
+    Success(SuccessReply<'a>),
+    Denied(DeniedReply<'a>),
+    ResyncRequired(ResyncReply<'a>),
+    TimeSyncRequest(TimeSyncRequest<'a>),
+    TimeSync(TimeSyncReply<'a>),
+    RangeChallenge(RangeBit),
+    RangeResponse(RangeBit),
+    RangeProof(RangeProof<'a>),
+}
+
+// Length of the freshness token in a Command of `mode`
+fn token_length(mode: Mode) -> usize {
+    match mode {
+        Mode::Timestamp => TIME_LENGTH,
+        Mode::Challenge => NONCE_LENGTH,
+        Mode::Counter => COUNTER_LENGTH,
+    }
+}
+
+// Splits a payload of `body_length` bytes followed by a non-empty signature
+fn split<'a>(
+    frame: &Frame<'a>,
+    body_length: usize,
+) -> Result<(&'a [u8], Signature<'a>), ProtocolError> {
+    if frame.payload.len() <= body_length {
+        return Err(ProtocolError::Malformed);
+    }
+    let (body, bytes) = frame.payload.split_at(body_length);
+    let signature = Signature {
+        algorithm: frame.algorithm,
+        covers: frame.signed(body_length),
+        bytes,
+    };
+    Ok((body, signature))
+}
+
+fn nonce(bytes: &[u8]) -> Result<&[u8; NONCE_LENGTH], ProtocolError> {
+    <&[u8; NONCE_LENGTH]>::try_from(bytes).map_err(|_| ProtocolError::Malformed)
+}
+
+fn range_bit(payload: &[u8]) -> Result<RangeBit, ProtocolError> {
+    match *payload {
+        [round, bit] if round as usize <= RANGE_ROUNDS && bit <= 1 => Ok(RangeBit {
+            round,
+            bit: bit == 1,
+        }),
+        _ => Err(ProtocolError::Malformed),
+    }
+}
+
+impl<'a> Message<'a> {
+    /// Parses a complete frame sent by a pair in `mode`, which sets the Command token length
+    pub fn parse(bytes: &'a [u8], mode: Mode) -> Result<Message<'a>, ProtocolError> {
+        Message::from_frame(&frame::decode(bytes)?, mode)
+    }
+
+    /// Parses the payload of an already decoded frame
+    pub fn from_frame(frame: &Frame<'a>, mode: Mode) -> Result<Message<'a>, ProtocolError> {
+        Ok(match frame.kind {
+            MessageKind::Hello => {
+                if !frame.payload.is_empty() {
+                    return Err(ProtocolError::Malformed);
+                }
+                Message::Hello
+            }
+            MessageKind::Challenge => {
+                let (body, signature) = split(frame, NONCE_LENGTH)?;
+                Message::Challenge(ChallengeReply {
+                    nonce: nonce(body)?,
+                    signature,
+                })
+            }
+            MessageKind::Command => {
+                let (body, signature) = split(frame, COMMAND_LENGTH + token_length(mode))?;
+                Message::Command(CommandRequest {
+                    command: Command::try_from(body[0])?,
+                    token: &body[COMMAND_LENGTH..],
+                    signature,
+                })
+            }
+            MessageKind::Success => {
+                let (body, signature) = split(frame, 1)?;
+                Message::Success(SuccessReply {
+                    state: CarState::from_byte(body[0])?,
+                    signature,
+                })
+            }
+            MessageKind::Denied => {
+                let (body, signature) = split(frame, 1)?;
+                Message::Denied(DeniedReply {
+                    reason: DenyReason::try_from(body[0])?,
+                    signature,
+                })
+            }
+            MessageKind::ResyncRequired => Message::ResyncRequired(ResyncReply {
+                signature: split(frame, 0)?.1,
+            }),
+            MessageKind::TimeSyncRequest => {
+                let (body, signature) = split(frame, NONCE_LENGTH)?;
+                Message::TimeSyncRequest(TimeSyncRequest {
+                    nonce: nonce(body)?,
+                    signature,
+                })
+            }
+            MessageKind::TimeSync => {
+                let (body, signature) = split(frame, TIME_LENGTH)?;
+                Message::TimeSync(TimeSyncReply {
+                    time: from_timestamp(body),
+                    signature,
+                })
+            }
+            MessageKind::RangeChallenge => Message::RangeChallenge(range_bit(frame.payload)?),
+            MessageKind::RangeResponse => Message::RangeResponse(range_bit(frame.payload)?),
+            MessageKind::RangeProof => {
+                let (body, signature) = split(frame, RANGE_MASK_LENGTH)?;
+                Message::RangeProof(RangeProof {
+                    mask: <&[u8; RANGE_MASK_LENGTH]>::try_from(body)
+                        .map_err(|_| ProtocolError::Malformed)?,
+                    signature,
+                })
+            }
+        })
+    }
+
+    pub fn kind(&self) -> MessageKind {
+        match self {
+            Message::Hello => MessageKind::Hello,
+            Message::Challenge(_) => MessageKind::Challenge,
+            Message::Command(_) => MessageKind::Command,
+            Message::Success(_) => MessageKind::Success,
+            Message::Denied(_) => MessageKind::Denied,
+            Message::ResyncRequired(_) => MessageKind::ResyncRequired,
+            Message::TimeSyncRequest(_) => MessageKind::TimeSyncRequest,
+            Message::TimeSync(_) => MessageKind::TimeSync,
+            Message::RangeChallenge(_) => MessageKind::RangeChallenge,
+            Message::RangeResponse(_) => MessageKind::RangeResponse,
+            Message::RangeProof(_) => MessageKind::RangeProof,
+        }
+    }
+}
@@ -0,0 +1,170 @@
+//! Unlocks over a simulated radio [`Channel`], to see how the protocol and the keychain's
+//! [`RetryPolicy`] cope with a bad one.
+//!
+//! Every run pairs a fresh car and keychain, presses Open once and lets simulated time pass,
+//! from one frame arrival or retry to the next, until the keychain is confirmed or gives up.
+//! Runs are repeatable: the same seed loses and damages the same frames.
+
+use crate::clock::{Clock, MockClock};
+use crate::crypto::{Algorithm, Backend};
+use crate::transport::{serve_all, Address, Channel, LossyEther, Transport};
+use crate::{
+    Car, Command, Keychain, LockState, MessageProcessor, Mode, ProtocolError, RetryPolicy,
+};
+use std::fmt;
+use std::time::Duration;
+
+/// Unlocks to run over a channel, see the [module documentation](self)
+#[derive(Clone, Copy, Debug)]
+pub struct Simulation {
+    mode: Mode,
+    algorithm: Algorithm,
+    channel: Channel,
+    retry: RetryPolicy,
+    seed: u64,
+}
+
+/// What came of the runs of a [`Simulation`]
+#[derive(Clone, PartialEq, Debug)]
+pub struct Report {
+    pub runs: usize,
+    pub confirmed: usize, // runs in which the keychain saw the car's Success
+    pub unlocked: usize,  // runs that left the car unlocked, confirmed or not
+    pub presses: usize,   // attempts across all runs
+    pub latencies: Vec<Duration>, // from the first press to the confirmation, ascending
+}
+
+impl Simulation {
+    /// Simulates pairs of `mode` and `algorithm` talking over `channel`
+    pub fn new(mode: Mode, algorithm: Algorithm, channel: Channel) -> Simulation {
+        Simulation {
+            mode,
+            algorithm,
+            channel,
+            retry: RetryPolicy::default(),
+            seed: 0,
+        }
+    }
+
+    /// Replaces the keychain's default [`RetryPolicy`]
+    pub fn with_retry(mut self, retry: RetryPolicy) -> Simulation {
+        self.retry = retry;
+        self
+    }
+
+    /// Seeds the channel's random generator, runs differ from each other but not between
+    /// simulations with the same seed
+    pub fn with_seed(mut self, seed: u64) -> Simulation {
+        self.seed = seed;
+        self
+    }
+
+    /// Unlocks `runs` times with keys generated once by `B`
+    pub fn run<B: Backend>(&self, runs: usize) -> Result<Report, ProtocolError> {
+        let (car_public, car_private) = B::generate(self.algorithm)?;
+        let (keychain_public, keychain_private) = B::generate(self.algorithm)?;
+        let mut report = Report {
+            runs,
+            confirmed: 0,
+            unlocked: 0,
+            presses: 0,
+            latencies: Vec::new(),
+        };
+        for run in 0..runs {
+            let clock = MockClock::new();
+            // a timestamp of zero would look like one the car has long forgotten
+            clock.advance(Duration::from_secs(1));
+            let mut car = Car::<B>::new(&car_private, &keychain_public, self.mode, self.algorithm)?
+                .with_clock(Box::new(clock.clone()));
+            let mut keychain =
+                Keychain::<B>::new(&keychain_private, &car_public, self.mode, self.algorithm)?
+                    .with_clock(Box::new(clock.clone()))
+                    .with_retry(self.retry);
+            let ether = LossyEther::new(
+                self.channel,
+                self.seed.wrapping_add(run as u64),
+                clock.clone(),
+            );
+            let (mut car_radio, mut keychain_radio) = (ether.connect(), ether.connect());
+
+            let start = clock.now();
+            // the keychain presses again once this passes, on the same clock
+            let mut retry_at = start + self.retry.timeout;
+            let frame = keychain.command(Command::Open)?;
+            keychain_radio
+                .send(Address::BROADCAST, &frame)
+                .expect("the ether does not fail");
+            loop {
+                serve_all(&mut [
+                    (&mut car as &mut dyn MessageProcessor, &mut car_radio),
+                    (&mut keychain, &mut keychain_radio),
+                ])
+                .expect("the ether does not fail");
+                if keychain.confirmed() {
+                    report.latencies.push(clock.now() - start);
+                    break;
+                }
+                if let Some(frame) = keychain.poll()? {
+                    keychain_radio
+                        .send(Address::BROADCAST, &frame)
+                        .expect("the ether does not fail");
+                    retry_at = clock.now() + self.retry.timeout;
+                }
+                let mut next = ether.next_arrival();
+                if keychain.waiting() {
+                    next = Some(next.map_or(retry_at, |arrival| arrival.min(retry_at)));
+                }
+                match next {
+                    Some(next) => clock.advance(next.saturating_sub(clock.now())),
+                    None => break,
+                }
+            }
+            report.confirmed += keychain.confirmed() as usize;
+            report.unlocked += (car.state().lock == LockState::Unlocked) as usize;
+            report.presses += keychain.attempts() as usize;
+        }
+        report.latencies.sort();
+        Ok(report)
+    }
+}
+
+impl Report {
+    /// Share of runs the keychain saw confirmed, from 0 to 1
+    pub fn success_rate(&self) -> f64 {
+        self.confirmed as f64 / self.runs.max(1) as f64
+    }
+
+    /// Latency that `quantile` of the confirmed runs did not exceed, e.g. 0.5 for the median
+    pub fn latency(&self, quantile: f64) -> Option<Duration> {
+        let last = self.latencies.len().checked_sub(1)?;
+        Some(self.latencies[(last as f64 * quantile.clamp(0.0, 1.0)).round() as usize])
+    }
+
+    /// Average presses per run
+    pub fn mean_presses(&self) -> f64 {
+        self.presses as f64 / self.runs.max(1) as f64
+    }
+}
+
+impl fmt::Display for Report {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "{} runs, {:.1}% confirmed, {:.1}% unlocked, {:.2} presses per run",
+            self.runs,
+            100.0 * self.success_rate(),
+            100.0 * self.unlocked as f64 / self.runs.max(1) as f64,
+            self.mean_presses()
+        )?;
+        if let (Some(median), Some(p95), Some(max)) =
+            (self.latency(0.5), self.latency(0.95), self.latency(1.0))
+        {
+            write!(
+                f,
+                ", latency median {:?}, 95th percentile {:?}, max {:?}",
+                median, p95, max
+            )?;
+        }
+        Ok(())
+    }
+}
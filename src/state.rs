@@ -0,0 +1,221 @@
+use crate::{Command, ProtocolError, AUTO_RELOCK};
+use std::time::Duration;
+
+/// Whether the doors are locked and what the alarm is doing
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum LockState {
+    Unlocked = 0, // doors open to anyone, auto-relock may be running
+    Locked = 1,   // doors locked, intrusion sensors off, e.g. while the engine runs remotely
+    Armed = 2,    // doors locked, opening one sounds the alarm
+    Alarm = 3,    // alarm sounding until the keychain opens, locks or presses panic again
+}
+
+/// What the car reports back in every Success, packed into one byte on the wire
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub struct CarState {
+    pub lock: LockState,
+    pub engine_running: bool,
+}
+
+/// What made the car change its state
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum Trigger {
+    Command(Command), // authenticated command from the keychain
+    AutoRelock,       // nobody opened a door in time after unlocking
+    Door,             // door opened while the car was armed
+}
+
+/// Something the car did, handed to every listener so integrators can drive
+/// actuators or keep a log
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum Event {
+    Transition {
+        from: CarState,
+        to: CarState,
+        trigger: Trigger,
+    },
+    Pulse(Command), // one-shot actuator with no lasting state: trunk, windows, horn and lights
+}
+
+/// Callback attached to a car with [`Car::with_listener`](crate::Car::with_listener)
+pub type Listener = Box<dyn FnMut(&Event)>;
+
+const LOCK_MASK: u8 = 0b11;
+const ENGINE_RUNNING: u8 = 1 << 2;
+
+impl Default for CarState {
+    fn default() -> CarState {
+        CarState {
+            lock: LockState::Armed,
+            engine_running: false,
+        }
+    }
+}
+
+impl CarState {
+    pub fn to_byte(self) -> u8 {
+        let mut byte = self.lock as u8;
+        if self.engine_running {
+            byte |= ENGINE_RUNNING;
+        }
+        byte
+    }
+
+    pub fn from_byte(byte: u8) -> Result<CarState, ProtocolError> {
+        if byte & !(LOCK_MASK | ENGINE_RUNNING) != 0 {
+            return Err(ProtocolError::Malformed);
+        }
+        let lock = match byte & LOCK_MASK {
+            0 => LockState::Unlocked,
+            1 => LockState::Locked,
+            2 => LockState::Armed,
+            _ => LockState::Alarm,
+        };
+        Ok(CarState {
+            lock,
+            engine_running: byte & ENGINE_RUNNING != 0,
+        })
+    }
+
+    // How the doors end up once they are locked, sensors stay off while the engine runs
+    fn secured(self) -> LockState {
+        if self.engine_running {
+            LockState::Locked
+        } else {
+            LockState::Armed
+        }
+    }
+}
+
+// Runs the car's state transitions and its auto-relock timer
+pub(crate) struct StateMachine {
+    state: CarState,
+    before_alarm: LockState, // restored when panic is pressed again
+    relock_after: Option<Duration>,
+    relock_at: Option<Duration>, // deadline of the running auto-relock timer
+    listeners: Vec<Listener>,
+}
+
+impl StateMachine {
+    pub(crate) fn new() -> StateMachine {
+        StateMachine {
+            state: CarState::default(),
+            before_alarm: LockState::Armed,
+            relock_after: Some(AUTO_RELOCK),
+            relock_at: None,
+            listeners: Vec::new(),
+        }
+    }
+
+    pub(crate) fn state(&self) -> CarState {
+        self.state
+    }
+
+    pub(crate) fn set_auto_relock(&mut self, after: Option<Duration>) {
+        self.relock_after = after;
+        self.relock_at = None;
+    }
+
+    pub(crate) fn listen(&mut self, listener: Listener) {
+        self.listeners.push(listener);
+    }
+
+    fn emit(&mut self, event: Event) {
+        for listener in self.listeners.iter_mut() {
+            listener(&event);
+        }
+    }
+
+    fn transition(&mut self, to: CarState, trigger: Trigger) {
+        let from = self.state;
+        if to.lock == LockState::Alarm && from.lock != LockState::Alarm {
+            self.before_alarm = from.lock;
+        }
+        if to.lock != LockState::Unlocked {
+            self.relock_at = None;
+        }
+        self.state = to;
+        if from != to {
+            self.emit(Event::Transition { from, to, trigger });
+        }
+    }
+
+    /// Relocks the car if the auto-relock timer has run out by `now`
+    pub(crate) fn poll(&mut self, now: Duration) {
+        match self.relock_at {
+            Some(deadline) if now >= deadline => {
+                let to = CarState {
+                    lock: self.state.secured(),
+                    ..self.state
+                };
+                self.transition(to, Trigger::AutoRelock);
+            }
+            _ => {}
+        }
+    }
+
+    /// Reports a door opening: stops the auto-relock, or sounds the alarm if armed
+    pub(crate) fn door_opened(&mut self) {
+        match self.state.lock {
+            LockState::Unlocked => self.relock_at = None,
+            LockState::Armed => {
+                let to = CarState {
+                    lock: LockState::Alarm,
+                    ..self.state
+                };
+                self.transition(to, Trigger::Door);
+            }
+            LockState::Locked | LockState::Alarm => {}
+        }
+    }
+
+    /// Carries out `command` received at `now`, fails if the car cannot do it in its current state
+    pub(crate) fn apply(
+        &mut self,
+        command: Command,
+        now: Duration,
+    ) -> Result<CarState, ProtocolError> {
+        let mut to = self.state;
+        match command {
+            Command::Open => to.lock = LockState::Unlocked,
+            Command::Lock => to.lock = to.secured(),
+            Command::EngineStart => {
+                if to.engine_running || to.lock == LockState::Unlocked {
+                    return Err(ProtocolError::Unavailable);
+                }
+                to.engine_running = true;
+                if to.lock == LockState::Armed {
+                    to.lock = LockState::Locked;
+                }
+            }
+            Command::EngineStop => {
+                if !to.engine_running {
+                    return Err(ProtocolError::Unavailable);
+                }
+                to.engine_running = false;
+                if to.lock == LockState::Locked {
+                    to.lock = LockState::Armed;
+                }
+            }
+            Command::Panic => {
+                to.lock = match (to.lock, self.before_alarm) {
+                    (LockState::Alarm, LockState::Unlocked) => LockState::Unlocked,
+                    (LockState::Alarm, _) => to.secured(),
+                    _ => LockState::Alarm,
+                }
+            }
+            Command::TrunkRelease | Command::WindowClose | Command::Locate => {
+                self.emit(Event::Pulse(command));
+                return Ok(self.state);
+            }
+        }
+        let from = self.state.lock;
+        self.transition(to, Trigger::Command(command));
+        // the timer runs however the car came to be unlocked, and Open restarts it
+        let unlocked = self.state.lock == LockState::Unlocked;
+        if unlocked && (from != LockState::Unlocked || command == Command::Open) {
+            self.relock_at = self.relock_after.map(|after| now + after);
+        }
+        Ok(self.state)
+    }
+}
@@ -0,0 +1,189 @@
+use super::{Address, Transport};
+use std::io::{self, Read, Write};
+use std::os::unix::net::{UnixListener, UnixStream};
+use std::path::Path;
+use std::sync::{Arc, Mutex};
+use std::thread;
+use std::time::Duration;
+
+// Every message on a socket to or from the broker is [address: 2][length: 2][frame],
+// the destination on the way in and the sender on the way out. A device is told its own
+// address as a bare [address: 2] once it connects.
+const ENVELOPE_LENGTH: usize = 4;
+/// How long [`AirTransport::receive`](Transport::receive) waits for a frame by default
+pub const AIR_RECEIVE_TIMEOUT: Duration = Duration::from_millis(50);
+
+fn write_message(stream: &mut UnixStream, address: Address, frame: &[u8]) -> io::Result<()> {
+    if frame.len() > u16::MAX as usize {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidInput,
+            "frame too long",
+        ));
+    }
+    let mut message = Vec::with_capacity(ENVELOPE_LENGTH + frame.len());
+    message.extend_from_slice(&address.0.to_be_bytes());
+    message.extend_from_slice(&(frame.len() as u16).to_be_bytes());
+    message.extend_from_slice(frame);
+    stream.write_all(&message)
+}
+
+// Takes the first complete message off `buffer`
+fn take_message(buffer: &mut Vec<u8>) -> Option<(Address, Vec<u8>)> {
+    if buffer.len() < ENVELOPE_LENGTH {
+        return None;
+    }
+    let length = u16::from_be_bytes([buffer[2], buffer[3]]) as usize;
+    if buffer.len() < ENVELOPE_LENGTH + length {
+        return None;
+    }
+    let address = Address(u16::from_be_bytes([buffer[0], buffer[1]]));
+    let frame = buffer[ENVELOPE_LENGTH..ENVELOPE_LENGTH + length].to_vec();
+    buffer.drain(..ENVELOPE_LENGTH + length);
+    Some((address, frame))
+}
+
+/// Broker emulating the shared radio medium for devices in separate processes: every
+/// device connects to its Unix socket, and every frame one of them sends reaches all the
+/// others, or just the one it is addressed to
+pub struct Air {
+    listener: UnixListener,
+}
+
+// A device's socket, locked only while a message is written to it
+type Device = Arc<Mutex<UnixStream>>;
+// Devices currently on the air, by address
+type Devices = Arc<Mutex<Vec<(Address, Device)>>>;
+
+impl Air {
+    /// Listens on a Unix socket at `path`, replacing whatever file was there
+    pub fn bind<P: AsRef<Path>>(path: P) -> io::Result<Air> {
+        let path = path.as_ref();
+        if path.exists() {
+            std::fs::remove_file(path)?;
+        }
+        Ok(Air {
+            listener: UnixListener::bind(path)?,
+        })
+    }
+
+    /// Connects devices and relays their frames until the listener fails
+    pub fn run(self) -> io::Result<()> {
+        let devices: Devices = Arc::default();
+        let mut next = 0;
+        for stream in self.listener.incoming() {
+            let mut stream = stream?;
+            let address = Address(next);
+            next = (next + 1) % Address::BROADCAST.0;
+            if stream.write_all(&address.0.to_be_bytes()).is_err() {
+                continue;
+            }
+            devices
+                .lock()
+                .unwrap()
+                .push((address, Arc::new(Mutex::new(stream.try_clone()?))));
+            let devices = devices.clone();
+            thread::spawn(move || relay(address, stream, devices));
+        }
+        Ok(())
+    }
+}
+
+// Hands every frame `from` sends to the devices it is meant for, until it disconnects
+fn relay(from: Address, mut stream: UnixStream, devices: Devices) {
+    let mut buffer = Vec::new();
+    let mut chunk = [0u8; 1024];
+    loop {
+        match stream.read(&mut chunk) {
+            Ok(0) | Err(_) => break,
+            Ok(length) => buffer.extend_from_slice(&chunk[..length]),
+        }
+        while let Some((to, frame)) = take_message(&mut buffer) {
+            // a slow device must not hold up the others, so none is written to with them locked
+            let recipients: Vec<Device> = devices
+                .lock()
+                .unwrap()
+                .iter()
+                .filter(|(address, _)| {
+                    *address != from && (to == Address::BROADCAST || to == *address)
+                })
+                .map(|(_, device)| device.clone())
+                .collect();
+            let gone: Vec<Device> = recipients
+                .into_iter()
+                .filter(|device| {
+                    let mut stream = device.lock().unwrap();
+                    write_message(&mut stream, from, &frame).is_err()
+                })
+                .collect();
+            // a device that cannot be written to has left the air
+            if !gone.is_empty() {
+                devices
+                    .lock()
+                    .unwrap()
+                    .retain(|(_, device)| !gone.iter().any(|left| Arc::ptr_eq(left, device)));
+            }
+        }
+    }
+    devices
+        .lock()
+        .unwrap()
+        .retain(|(address, _)| *address != from);
+}
+
+/// A device's connection to an [`Air`] broker
+pub struct AirTransport {
+    stream: UnixStream,
+    address: Address,
+    buffer: Vec<u8>, // bytes received but not yet a whole message
+}
+
+impl AirTransport {
+    /// Connects to the broker listening at `path`
+    pub fn connect<P: AsRef<Path>>(path: P) -> io::Result<AirTransport> {
+        let mut stream = UnixStream::connect(path)?;
+        let mut address = [0u8; 2];
+        stream.read_exact(&mut address)?;
+        stream.set_read_timeout(Some(AIR_RECEIVE_TIMEOUT))?;
+        Ok(AirTransport {
+            stream,
+            address: Address(u16::from_be_bytes(address)),
+            buffer: Vec::new(),
+        })
+    }
+
+    /// Waits up to `timeout` for a frame in `receive`, must not be zero
+    pub fn with_timeout(self, timeout: Duration) -> io::Result<AirTransport> {
+        self.stream.set_read_timeout(Some(timeout))?;
+        Ok(self)
+    }
+}
+
+impl Transport for AirTransport {
+    fn address(&self) -> Address {
+        self.address
+    }
+
+    fn send(&mut self, to: Address, frame: &[u8]) -> io::Result<()> {
+        write_message(&mut self.stream, to, frame)
+    }
+
+    fn receive(&mut self) -> io::Result<Option<(Address, Vec<u8>)>> {
+        let mut chunk = [0u8; 1024];
+        loop {
+            if let Some(message) = take_message(&mut self.buffer) {
+                return Ok(Some(message));
+            }
+            match self.stream.read(&mut chunk) {
+                Ok(0) => return Err(io::ErrorKind::UnexpectedEof.into()),
+                Ok(length) => self.buffer.extend_from_slice(&chunk[..length]),
+                Err(e)
+                    if e.kind() == io::ErrorKind::WouldBlock
+                        || e.kind() == io::ErrorKind::TimedOut =>
+                {
+                    return Ok(None)
+                }
+                Err(e) => return Err(e),
+            }
+        }
+    }
+}
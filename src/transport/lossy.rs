@@ -0,0 +1,214 @@
+use super::{Address, Transport};
+use crate::clock::{Clock, MockClock};
+use std::cell::RefCell;
+use std::io;
+use std::rc::Rc;
+use std::time::Duration;
+
+/// How long a frame takes from its sender to a receiver
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum Latency {
+    Fixed(Duration),
+    Uniform(Duration, Duration), // anywhere between the two, equally likely
+    Exponential { min: Duration, mean: Duration }, // `min` plus a long tail averaging `mean`
+}
+
+/// What a radio channel does to the frames crossing it, independently for every receiver.
+/// The default is a perfect channel.
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub struct Channel {
+    pub loss: f64,           // probability that a frame never arrives
+    pub duplication: f64,    // probability that a frame arrives twice
+    pub reordering: f64,     // probability that a frame is held back behind later ones
+    pub latency: Latency,    // of every copy that arrives
+    pub bit_error_rate: f64, // probability that any one bit is flipped on the way
+}
+
+impl Default for Channel {
+    fn default() -> Channel {
+        Channel {
+            loss: 0.0,
+            duplication: 0.0,
+            reordering: 0.0,
+            latency: Latency::Fixed(Duration::ZERO),
+            bit_error_rate: 0.0,
+        }
+    }
+}
+
+// SplitMix64, plenty for noise and the same sequence for the same seed everywhere
+struct Rng(u64);
+
+impl Rng {
+    fn next(&mut self) -> u64 {
+        self.0 = self.0.wrapping_add(0x9e37_79b9_7f4a_7c15);
+        let mut z = self.0;
+        z = (z ^ (z >> 30)).wrapping_mul(0xbf58_476d_1ce4_e5b9);
+        z = (z ^ (z >> 27)).wrapping_mul(0x94d0_49bb_1331_11eb);
+        z ^ (z >> 31)
+    }
+
+    // Uniform in [0, 1)
+    fn unit(&mut self) -> f64 {
+        (self.next() >> 11) as f64 / (1u64 << 53) as f64
+    }
+
+    fn chance(&mut self, probability: f64) -> bool {
+        probability > 0.0 && self.unit() < probability
+    }
+
+    fn latency(&mut self, latency: Latency) -> Duration {
+        match latency {
+            Latency::Fixed(latency) => latency,
+            Latency::Uniform(min, max) => min + (max.saturating_sub(min)).mul_f64(self.unit()),
+            Latency::Exponential { min, mean } => min + mean.mul_f64(-(1.0 - self.unit()).ln()),
+        }
+    }
+}
+
+// A frame on its way to one receiver
+struct InFlight {
+    arrival: Duration,
+    sequence: u64, // keeps frames arriving at the same time in the order they were sent
+    from: Address,
+    frame: Vec<u8>,
+}
+
+struct Medium {
+    channel: Channel,
+    rng: Rng,
+    clock: MockClock,
+    sent: u64,
+    inboxes: Vec<Vec<InFlight>>, // one per connected device, by address
+    log: Vec<Vec<u8>>,           // every frame transmitted, in order, as it was sent
+}
+
+impl Medium {
+    // Puts a copy of `frame` on its way to `to` as the channel sees fit
+    fn deliver(&mut self, from: Address, to: Address, frame: &[u8]) {
+        if self.rng.chance(self.channel.loss) {
+            return;
+        }
+        let copies = if self.rng.chance(self.channel.duplication) {
+            2
+        } else {
+            1
+        };
+        for _ in 0..copies {
+            let mut latency = self.rng.latency(self.channel.latency);
+            if self.rng.chance(self.channel.reordering) {
+                latency += self.rng.latency(self.channel.latency) + Duration::from_nanos(1);
+            }
+            let mut frame = frame.to_vec();
+            for byte in frame.iter_mut() {
+                for bit in 0..8 {
+                    if self.rng.chance(self.channel.bit_error_rate) {
+                        *byte ^= 1 << bit;
+                    }
+                }
+            }
+            self.sent += 1;
+            let arrival = self.clock.now() + latency;
+            self.inboxes[to.0 as usize].push(InFlight {
+                arrival,
+                sequence: self.sent,
+                from,
+                frame,
+            });
+        }
+    }
+}
+
+/// Broadcast medium that loses, duplicates, reorders, delays and damages frames according to
+/// a [`Channel`], driven by a seeded random generator so every run can be repeated. Frames
+/// arrive once the shared [`MockClock`] reaches their arrival time, clones share the medium.
+#[derive(Clone)]
+pub struct LossyEther {
+    medium: Rc<RefCell<Medium>>,
+}
+
+impl LossyEther {
+    pub fn new(channel: Channel, seed: u64, clock: MockClock) -> LossyEther {
+        LossyEther {
+            medium: Rc::new(RefCell::new(Medium {
+                channel,
+                rng: Rng(seed),
+                clock,
+                sent: 0,
+                inboxes: Vec::new(),
+                log: Vec::new(),
+            })),
+        }
+    }
+
+    /// Connects a new device, addresses are handed out from 0
+    pub fn connect(&self) -> LossyTransport {
+        let mut medium = self.medium.borrow_mut();
+        medium.inboxes.push(Vec::new());
+        LossyTransport {
+            address: Address(medium.inboxes.len() as u16 - 1),
+            medium: self.medium.clone(),
+        }
+    }
+
+    /// How many frames are still on their way
+    pub fn in_flight(&self) -> usize {
+        self.medium.borrow().inboxes.iter().map(Vec::len).sum()
+    }
+
+    /// When the next frame on its way arrives
+    pub fn next_arrival(&self) -> Option<Duration> {
+        let medium = self.medium.borrow();
+        medium
+            .inboxes
+            .iter()
+            .flatten()
+            .map(|frame| frame.arrival)
+            .min()
+    }
+
+    /// Every frame transmitted so far, in order
+    pub fn log(&self) -> Vec<Vec<u8>> {
+        self.medium.borrow().log.clone()
+    }
+}
+
+/// A device's connection to a [`LossyEther`]
+pub struct LossyTransport {
+    address: Address,
+    medium: Rc<RefCell<Medium>>,
+}
+
+impl Transport for LossyTransport {
+    fn address(&self) -> Address {
+        self.address
+    }
+
+    fn send(&mut self, to: Address, frame: &[u8]) -> io::Result<()> {
+        let mut medium = self.medium.borrow_mut();
+        medium.log.push(frame.to_vec());
+        for address in 0..medium.inboxes.len() as u16 {
+            let address = Address(address);
+            if address != self.address && (to == Address::BROADCAST || to == address) {
+                medium.deliver(self.address, address, frame);
+            }
+        }
+        Ok(())
+    }
+
+    fn receive(&mut self) -> io::Result<Option<(Address, Vec<u8>)>> {
+        let mut medium = self.medium.borrow_mut();
+        let now = medium.clock.now();
+        let inbox = &mut medium.inboxes[self.address.0 as usize];
+        let next = inbox
+            .iter()
+            .enumerate()
+            .filter(|(_, frame)| frame.arrival <= now)
+            .min_by_key(|(_, frame)| (frame.arrival, frame.sequence))
+            .map(|(index, _)| index);
+        Ok(next.map(|index| {
+            let frame = inbox.swap_remove(index);
+            (frame.from, frame.frame)
+        }))
+    }
+}
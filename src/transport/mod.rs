@@ -0,0 +1,146 @@
+//! Media that carry frames between devices.
+//!
+//! A [`Transport`] is one device's connection to a medium. [`serve`] lets a
+//! [`MessageProcessor`] answer whatever its transport received, so the same car and keychain
+//! run over any medium. [`Ether`] is the in-memory one: every frame broadcast on it reaches
+//! every other device, never its sender. [`UdpTransport`] carries frames between processes
+//! on one machine, and so does [`AirTransport`] through an [`Air`] broker that any number of
+//! devices share. [`LossyEther`] is an in-memory medium that behaves like a real radio
+//! [`Channel`], losing, repeating, delaying and damaging frames.
+
+#[cfg(unix)]
+mod air;
+mod lossy;
+mod udp;
+
+#[cfg(unix)]
+pub use self::air::{Air, AirTransport, AIR_RECEIVE_TIMEOUT};
+pub use self::lossy::{Channel, Latency, LossyEther, LossyTransport};
+pub use self::udp::{UdpTransport, UDP_RECEIVE_TIMEOUT};
+
+use crate::MessageProcessor;
+use std::cell::RefCell;
+use std::collections::VecDeque;
+use std::io;
+use std::rc::Rc;
+
+/// Where a frame is sent to or came from
+#[derive(Clone, Copy, PartialEq, Eq, Hash, Debug)]
+pub struct Address(pub u16);
+
+impl Address {
+    /// Every device on the medium except the sender
+    pub const BROADCAST: Address = Address(u16::MAX);
+}
+
+/// One device's connection to a medium
+pub trait Transport {
+    /// Address other devices reach this one at
+    fn address(&self) -> Address;
+
+    /// Transmits `frame` to `to`, frames for addresses nobody listens on are lost
+    fn send(&mut self, to: Address, frame: &[u8]) -> io::Result<()>;
+
+    /// Returns the next frame for this device and its sender, `None` once nothing is waiting
+    fn receive(&mut self) -> io::Result<Option<(Address, Vec<u8>)>>;
+}
+
+/// Lets `device` answer every frame waiting on `transport`, returns how many there were.
+/// Replies and refusals are broadcast like any radio transmission.
+pub fn serve(
+    device: &mut dyn MessageProcessor,
+    transport: &mut dyn Transport,
+) -> io::Result<usize> {
+    let mut handled = 0;
+    while let Some((_, frame)) = transport.receive()? {
+        handled += 1;
+        let reply = match device.process(&frame) {
+            Ok(reply) => reply,
+            Err(e) => {
+                log::info!("frame refused: {}", e);
+                device.refusal(e)
+            }
+        };
+        if let Some(reply) = reply {
+            transport.send(Address::BROADCAST, &reply)?;
+        }
+    }
+    Ok(handled)
+}
+
+/// Serves every device from its own transport in turn until none of them receives anything
+pub fn serve_all(
+    stations: &mut [(&mut dyn MessageProcessor, &mut dyn Transport)],
+) -> io::Result<()> {
+    loop {
+        let mut handled = 0;
+        for (device, transport) in stations.iter_mut() {
+            handled += serve(&mut **device, &mut **transport)?;
+        }
+        if handled == 0 {
+            return Ok(());
+        }
+    }
+}
+
+#[derive(Default)]
+struct Medium {
+    inboxes: Vec<VecDeque<(Address, Vec<u8>)>>, // one per connected device, by address
+    log: Vec<Vec<u8>>,                          // every frame transmitted, in order
+}
+
+/// In-memory broadcast medium, clones share it
+#[derive(Clone, Default)]
+pub struct Ether {
+    medium: Rc<RefCell<Medium>>,
+}
+
+impl Ether {
+    pub fn new() -> Ether {
+        Ether::default()
+    }
+
+    /// Connects a new device, addresses are handed out from 0
+    pub fn connect(&self) -> EtherTransport {
+        let mut medium = self.medium.borrow_mut();
+        medium.inboxes.push(VecDeque::new());
+        EtherTransport {
+            address: Address(medium.inboxes.len() as u16 - 1),
+            medium: self.medium.clone(),
+        }
+    }
+
+    /// Every frame transmitted so far, in order
+    pub fn log(&self) -> Vec<Vec<u8>> {
+        self.medium.borrow().log.clone()
+    }
+}
+
+/// A device's connection to an [`Ether`]
+pub struct EtherTransport {
+    address: Address,
+    medium: Rc<RefCell<Medium>>,
+}
+
+impl Transport for EtherTransport {
+    fn address(&self) -> Address {
+        self.address
+    }
+
+    fn send(&mut self, to: Address, frame: &[u8]) -> io::Result<()> {
+        let mut medium = self.medium.borrow_mut();
+        medium.log.push(frame.to_vec());
+        for (address, inbox) in medium.inboxes.iter_mut().enumerate() {
+            let address = Address(address as u16);
+            if address != self.address && (to == Address::BROADCAST || to == address) {
+                inbox.push_back((self.address, frame.to_vec()));
+            }
+        }
+        Ok(())
+    }
+
+    fn receive(&mut self) -> io::Result<Option<(Address, Vec<u8>)>> {
+        let mut medium = self.medium.borrow_mut();
+        Ok(medium.inboxes[self.address.0 as usize].pop_front())
+    }
+}
@@ -0,0 +1,98 @@
+use super::{Address, Transport};
+use std::io;
+use std::net::{Ipv4Addr, SocketAddr, UdpSocket};
+use std::time::Duration;
+
+// Largest payload of a UDP datagram over IPv4
+const MAX_DATAGRAM: usize = 65_507;
+/// How long [`UdpTransport::receive`](Transport::receive) waits for a datagram by default
+pub const UDP_RECEIVE_TIMEOUT: Duration = Duration::from_millis(50);
+
+/// Devices on the loopback interface, one UDP socket each, addressed by port.
+/// Broadcasts go to every peer added by hand or heard from, never back to the sender.
+pub struct UdpTransport {
+    socket: UdpSocket,
+    address: Address,
+    peers: Vec<Address>,
+    buffer: Vec<u8>, // reused for every datagram received
+}
+
+impl UdpTransport {
+    /// Binds to `port` on 127.0.0.1, 0 picks a free port
+    pub fn bind(port: u16) -> io::Result<UdpTransport> {
+        let socket = UdpSocket::bind((Ipv4Addr::LOCALHOST, port))?;
+        socket.set_read_timeout(Some(UDP_RECEIVE_TIMEOUT))?;
+        let address = Address(socket.local_addr()?.port());
+        Ok(UdpTransport {
+            socket,
+            address,
+            peers: Vec::new(),
+            buffer: vec![0; MAX_DATAGRAM],
+        })
+    }
+
+    /// Adds a device that broadcasts reach before it was heard from
+    pub fn with_peer(mut self, peer: Address) -> UdpTransport {
+        self.add_peer(peer);
+        self
+    }
+
+    /// Waits up to `timeout` for a datagram in `receive`, must not be zero
+    pub fn with_timeout(self, timeout: Duration) -> io::Result<UdpTransport> {
+        self.socket.set_read_timeout(Some(timeout))?;
+        Ok(self)
+    }
+
+    fn add_peer(&mut self, peer: Address) {
+        if peer != self.address && peer != Address::BROADCAST && !self.peers.contains(&peer) {
+            self.peers.push(peer);
+        }
+    }
+
+    fn send_to(&self, to: Address, frame: &[u8]) -> io::Result<()> {
+        match self.socket.send_to(frame, (Ipv4Addr::LOCALHOST, to.0)) {
+            // nobody listening on that port, lost like a frame nobody receives
+            Err(e) if e.kind() == io::ErrorKind::ConnectionRefused => Ok(()),
+            result => result.map(|_| ()),
+        }
+    }
+}
+
+impl Transport for UdpTransport {
+    fn address(&self) -> Address {
+        self.address
+    }
+
+    fn send(&mut self, to: Address, frame: &[u8]) -> io::Result<()> {
+        if to != Address::BROADCAST {
+            return self.send_to(to, frame);
+        }
+        for &peer in &self.peers {
+            self.send_to(peer, frame)?;
+        }
+        Ok(())
+    }
+
+    fn receive(&mut self) -> io::Result<Option<(Address, Vec<u8>)>> {
+        loop {
+            let (length, from) = match self.socket.recv_from(&mut self.buffer) {
+                Ok(received) => received,
+                Err(e)
+                    if e.kind() == io::ErrorKind::WouldBlock
+                        || e.kind() == io::ErrorKind::TimedOut =>
+                {
+                    return Ok(None)
+                }
+                // an earlier datagram to a closed port bounced, nothing to do with this one
+                Err(e) if e.kind() == io::ErrorKind::ConnectionRefused => continue,
+                Err(e) => return Err(e),
+            };
+            let from = match from {
+                SocketAddr::V4(from) if from.ip().is_loopback() => Address(from.port()),
+                _ => continue,
+            };
+            self.add_peer(from);
+            return Ok(Some((from, self.buffer[..length].to_vec())));
+        }
+    }
+}
@@ -0,0 +1,45 @@
+#![cfg(unix)]
+
+extern crate keychain_protocol;
+
+mod common;
+
+use common::{car, confirmed, Daemon, Scratch};
+
+fn air(scratch: &Scratch) -> (Daemon, String) {
+    let socket = scratch.path("air.sock").to_string_lossy().into_owned();
+    (Daemon::spawn(env!("CARGO_BIN_EXE_air"), &[&socket]), socket)
+}
+
+#[test]
+fn pairs_share_the_air() {
+    let scratch = Scratch::new("air");
+    let (_air, socket) = air(&scratch);
+    let pairs = [Scratch::new("air-a"), Scratch::new("air-b")];
+    let _cars: Vec<Daemon> = pairs
+        .iter()
+        .map(|keys| car(keys, &["--air", &socket, "--generate"]))
+        .collect();
+
+    // each car only answers its own keychain; presses take turns as a car keeps a
+    // single outstanding challenge, which any Hello replaces
+    for keys in &pairs {
+        let output = common::keychain(keys, &["--air", &socket, "open", "lock"]);
+        assert!(output.status.success());
+        assert_eq!(confirmed(&output), 2);
+    }
+}
+
+#[test]
+fn keychain_without_its_car_goes_unanswered() {
+    let scratch = Scratch::new("air-lonely");
+    let (_air, socket) = air(&scratch);
+    let (ours, theirs) = (Scratch::new("air-ours"), Scratch::new("air-theirs"));
+    let _car = car(&ours, &["--air", &socket, "--generate"]);
+    drop(car(&theirs, &["--air", &socket, "--generate"]));
+
+    let output = common::keychain(&theirs, &["--air", &socket, "open"]);
+    assert_eq!(output.status.code(), Some(1));
+    let output = common::keychain(&ours, &["--air", &socket, "open"]);
+    assert!(output.status.success());
+}
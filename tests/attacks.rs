@@ -0,0 +1,342 @@
+// Which attacks the car defeats, in which mode, and what it takes to defeat the others
+
+extern crate keychain_protocol;
+
+use keychain_protocol::attack::{intercept, Antenna, Forward, Recorder, RollJam, Tactic, Tamper};
+use keychain_protocol::clock::MockClock;
+use keychain_protocol::crypto::{Algorithm, Backend};
+use keychain_protocol::frame;
+use keychain_protocol::transport::{serve_all, Address, Ether, EtherTransport, Transport};
+use keychain_protocol::{
+    make_key_car_pair, Car, Command, Event, FreshnessPolicy, Keychain, LockState, MessageKind,
+    MessageProcessor, Mode, AUTO_RELOCK,
+};
+use std::cell::RefCell;
+use std::rc::Rc;
+use std::time::Duration;
+
+const MODES: [Mode; 3] = [Mode::Timestamp, Mode::Challenge, Mode::Counter];
+// How often the scene looks at the devices while frames are on their way
+const STEP: Duration = Duration::from_millis(1);
+
+type Events = Rc<RefCell<Vec<Event>>>;
+
+// How the car is set up beyond its defaults
+#[derive(Clone, Copy, Default)]
+struct Hardening {
+    policy: FreshnessPolicy,
+    max_round_trip: Option<Duration>,
+}
+
+// A pair sharing one mock clock, the car logging its events
+fn pair<B: Backend>(mode: Mode, hardening: Hardening) -> (Car<B>, Keychain<B>, MockClock, Events) {
+    let clock = MockClock::new();
+    clock.advance(Duration::from_secs(1_600_000_000));
+    let events = Rc::new(RefCell::new(Vec::new()));
+    let log = events.clone();
+    let (car, keychain) = make_key_car_pair::<B>(mode, Algorithm::Ed25519).unwrap();
+    let car = car
+        .with_clock(Box::new(clock.clone()))
+        .with_policy(hardening.policy)
+        .with_distance_bounding(hardening.max_round_trip)
+        .with_listener(Box::new(move |event: &Event| log.borrow_mut().push(*event)));
+    (
+        car,
+        keychain.with_clock(Box::new(clock.clone())),
+        clock,
+        events,
+    )
+}
+
+// A car and its keychain out of each other's reach, each in its own ether with an antenna
+// of the attacker
+struct Scene<B: Backend, T: Tactic> {
+    car: Car<B>,
+    keychain: Keychain<B>,
+    clock: MockClock,
+    events: Events,
+    near_keychain: Antenna<T>,
+    near_car: Antenna<T>,
+    radios: [EtherTransport; 4], // keychain, antenna near it, car, antenna near it
+}
+
+impl<B: Backend, T: Tactic> Scene<B, T> {
+    fn new(mode: Mode, hardening: Hardening, tactic: T, latency: Duration) -> Scene<B, T> {
+        let (car, keychain, clock, events) = pair::<B>(mode, hardening);
+        let (near_keychain, near_car) = intercept(tactic, latency, Box::new(clock.clone()));
+        let (keychain_side, car_side) = (Ether::new(), Ether::new());
+        Scene {
+            car,
+            keychain,
+            clock,
+            events,
+            near_keychain,
+            near_car,
+            radios: [
+                keychain_side.connect(),
+                keychain_side.connect(),
+                car_side.connect(),
+                car_side.connect(),
+            ],
+        }
+    }
+
+    // Presses `command` and lets everything happen until the keychain gives up or is confirmed
+    fn press(&mut self, command: Command) {
+        let frame = self.keychain.command(command).unwrap();
+        self.radios[0].send(Address::BROADCAST, &frame).unwrap();
+        loop {
+            let [keychain, near_keychain, car, near_car] = &mut self.radios;
+            serve_all(&mut [
+                (&mut self.keychain as &mut dyn MessageProcessor, keychain),
+                (&mut self.near_keychain, near_keychain),
+            ])
+            .unwrap();
+            serve_all(&mut [
+                (&mut self.car as &mut dyn MessageProcessor, car),
+                (&mut self.near_car, near_car),
+            ])
+            .unwrap();
+            let mut sent = false;
+            while let Some(frame) = self.near_keychain.poll() {
+                near_keychain.send(Address::BROADCAST, &frame).unwrap();
+                sent = true;
+            }
+            while let Some(frame) = self.near_car.poll() {
+                near_car.send(Address::BROADCAST, &frame).unwrap();
+                sent = true;
+            }
+            if let Some(frame) = self.keychain.poll().unwrap() {
+                keychain.send(Address::BROADCAST, &frame).unwrap();
+                sent = true;
+            }
+            if sent {
+                continue;
+            }
+            if self.near_keychain.pending() + self.near_car.pending() == 0
+                && !self.keychain.waiting()
+            {
+                return;
+            }
+            self.clock.advance(STEP);
+        }
+    }
+
+    // Has the attacker's antenna play `frame` to the car
+    fn inject(&mut self, frame: &[u8]) {
+        let [_, _, car, near_car] = &mut self.radios;
+        near_car.send(Address::BROADCAST, frame).unwrap();
+        serve_all(&mut [(&mut self.car as &mut dyn MessageProcessor, car)]).unwrap();
+    }
+
+    fn unlocked(&self) -> bool {
+        self.car.state().lock == LockState::Unlocked
+    }
+}
+
+// A freshness policy leaving no time for anything but the radio round trip
+fn tight() -> Hardening {
+    Hardening {
+        policy: FreshnessPolicy {
+            max_age: Duration::from_millis(50),
+            ..FreshnessPolicy::default()
+        },
+        ..Hardening::default()
+    }
+}
+
+// Distance bounding that allows for no more than the radio round trip
+fn bounded() -> Hardening {
+    Hardening {
+        max_round_trip: Some(Duration::from_millis(1)),
+        ..Hardening::default()
+    }
+}
+
+// Passive: whatever is recorded from an unlock does not unlock the car again, at once or later
+fn recorded_unlock_cannot_be_replayed<B: Backend>() {
+    for &mode in &MODES {
+        for &later in &[Duration::ZERO, Duration::from_secs(60)] {
+            let (mut car, mut keychain, clock, _) = pair::<B>(mode, Hardening::default());
+            let mut recorder = Recorder::new();
+            let ether = Ether::new();
+            let mut radios = [ether.connect(), ether.connect(), ether.connect()];
+            let mut attacker = ether.connect();
+            for &command in &[Command::Open, Command::Lock] {
+                let frame = keychain.command(command).unwrap();
+                radios[1].send(Address::BROADCAST, &frame).unwrap();
+                let [car_radio, keychain_radio, recorder_radio] = &mut radios;
+                serve_all(&mut [
+                    (&mut car as &mut dyn MessageProcessor, car_radio),
+                    (&mut keychain, keychain_radio),
+                    (&mut recorder, recorder_radio),
+                ])
+                .unwrap();
+            }
+            assert!(keychain.confirmed());
+            assert_eq!(car.state().lock, LockState::Armed);
+
+            clock.advance(later);
+            // everything the keychain sent up to the car's first Success
+            let unlock = recorder
+                .heard()
+                .iter()
+                .take_while(|frame| frame::decode(frame).unwrap().kind != MessageKind::Success);
+            for frame in unlock {
+                attacker.send(Address::BROADCAST, frame).unwrap();
+            }
+            serve_all(&mut [(&mut car as &mut dyn MessageProcessor, &mut radios[0])]).unwrap();
+            assert_eq!(car.state().lock, LockState::Armed, "{:?}", mode);
+        }
+    }
+}
+
+// Relay: nothing but time tells a relayed keychain from one in range, and the default
+// policy leaves plenty of it
+fn relay_unlocks_a_car_out_of_range<B: Backend>() {
+    for &mode in &MODES {
+        let mut scene = Scene::<B, _>::new(
+            mode,
+            Hardening::default(),
+            Forward,
+            Duration::from_millis(100),
+        );
+        scene.press(Command::Open);
+        assert!(scene.unlocked(), "{:?}", mode);
+        assert!(scene.keychain.confirmed());
+    }
+}
+
+// Hardened: a tight freshness policy defeats a slow relay in timestamp and challenge mode,
+// a counter carries no time at all. A relay as fast as the radio still gets through.
+fn tight_freshness_defeats_a_slow_relay<B: Backend>() {
+    for &(mode, defeated) in &[
+        (Mode::Timestamp, true),
+        (Mode::Challenge, true),
+        (Mode::Counter, false),
+    ] {
+        let mut scene = Scene::<B, _>::new(mode, tight(), Forward, Duration::from_millis(100));
+        scene.press(Command::Open);
+        assert_eq!(scene.unlocked(), !defeated, "{:?}", mode);
+
+        let mut scene = Scene::<B, _>::new(mode, tight(), Forward, Duration::ZERO);
+        scene.press(Command::Open);
+        assert!(scene.unlocked(), "{:?}", mode);
+    }
+}
+
+// RollJam: the owner presses until they give up, the car unlocks from an earlier press and the
+// attacker keeps the last one. Once the car relocked itself, only a counter still accepts it.
+fn rolljam_defeats_counter_mode<B: Backend>() {
+    for &(mode, defeated) in &[
+        (Mode::Timestamp, true),
+        (Mode::Challenge, true),
+        (Mode::Counter, false),
+    ] {
+        let mut scene =
+            Scene::<B, _>::new(mode, Hardening::default(), RollJam::new(), Duration::ZERO);
+        scene.press(Command::Open);
+        assert!(!scene.keychain.confirmed(), "{:?}", mode);
+
+        scene.clock.advance(AUTO_RELOCK);
+        scene.car.poll();
+        assert_eq!(scene.car.state().lock, LockState::Armed);
+        let kept = scene.near_car.tactic().take().unwrap();
+        scene.inject(&kept);
+        assert_eq!(scene.unlocked(), !defeated, "{:?}", mode);
+    }
+}
+
+// Hardened: distance bounding defeats a relay in every mode, however tight the attacker's
+// link. The owner's keychain in range is not bothered.
+fn distance_bounding_defeats_a_relay<B: Backend>() {
+    for &mode in &MODES {
+        let mut scene = Scene::<B, _>::new(mode, bounded(), Forward, Duration::from_millis(1));
+        scene.press(Command::Open);
+        assert!(!scene.unlocked(), "{:?}", mode);
+        assert!(!scene.keychain.confirmed());
+
+        let mut scene = Scene::<B, _>::new(mode, bounded(), Forward, Duration::ZERO);
+        scene.press(Command::Open);
+        assert!(scene.unlocked(), "{:?}", mode);
+        assert!(scene.keychain.confirmed());
+    }
+}
+
+// Hardened: the Command RollJam keeps is worthless without the keychain at hand to answer
+// the distance bounding, even in counter mode
+fn distance_bounding_defeats_rolljam<B: Backend>() {
+    for &mode in &MODES {
+        let mut scene = Scene::<B, _>::new(mode, bounded(), RollJam::new(), Duration::ZERO);
+        scene.press(Command::Open);
+        let kept = scene.near_car.tactic().take().unwrap();
+        scene.clock.advance(AUTO_RELOCK);
+        scene.inject(&kept);
+        assert!(!scene.unlocked(), "{:?}", mode);
+    }
+}
+
+// Active MITM: changing the command or the token of a Command in flight breaks its signature,
+// and so does cutting it short. The car neither answers nor does anything.
+fn tampered_commands_are_ignored<B: Backend>() {
+    let edits: [fn(&mut Vec<u8>); 3] = [
+        |payload| payload[0] = Command::TrunkRelease as u8,
+        |payload| payload[8] ^= 1,
+        |payload| payload.truncate(payload.len() - 1),
+    ];
+    for &mode in &MODES {
+        for &edit in &edits {
+            let tamper = Tamper::new(Box::new(edit));
+            let mut scene = Scene::<B, _>::new(mode, Hardening::default(), tamper, Duration::ZERO);
+            scene.press(Command::Open);
+            assert!(scene.events.borrow().is_empty(), "{:?}", mode);
+            assert!(!scene.keychain.confirmed());
+        }
+    }
+}
+
+macro_rules! backend_tests {
+    ($name:ident, $backend:ty) => {
+        mod $name {
+            #[test]
+            fn recorded_unlock_cannot_be_replayed() {
+                super::recorded_unlock_cannot_be_replayed::<$backend>();
+            }
+
+            #[test]
+            fn relay_unlocks_a_car_out_of_range() {
+                super::relay_unlocks_a_car_out_of_range::<$backend>();
+            }
+
+            #[test]
+            fn tight_freshness_defeats_a_slow_relay() {
+                super::tight_freshness_defeats_a_slow_relay::<$backend>();
+            }
+
+            #[test]
+            fn rolljam_defeats_counter_mode() {
+                super::rolljam_defeats_counter_mode::<$backend>();
+            }
+
+            #[test]
+            fn distance_bounding_defeats_a_relay() {
+                super::distance_bounding_defeats_a_relay::<$backend>();
+            }
+
+            #[test]
+            fn distance_bounding_defeats_rolljam() {
+                super::distance_bounding_defeats_rolljam::<$backend>();
+            }
+
+            #[test]
+            fn tampered_commands_are_ignored() {
+                super::tampered_commands_are_ignored::<$backend>();
+            }
+        }
+    };
+}
+
+#[cfg(feature = "openssl")]
+backend_tests!(openssl_backend, keychain_protocol::crypto::OpenSsl);
+#[cfg(feature = "rustcrypto")]
+backend_tests!(rustcrypto_backend, keychain_protocol::crypto::RustCrypto);
@@ -0,0 +1,98 @@
+// Helpers for the tests that run the binaries as separate processes
+#![allow(dead_code)]
+
+use std::env;
+use std::ffi::OsStr;
+use std::fs;
+use std::io::{BufRead, BufReader, Read};
+use std::path::{Path, PathBuf};
+use std::process::{self, Child, Command, Output, Stdio};
+use std::thread;
+
+// A directory for key files and sockets, removed when dropped
+pub struct Scratch(PathBuf);
+
+impl Scratch {
+    pub fn new(name: &str) -> Scratch {
+        let path = env::temp_dir().join(format!("keychain_protocol-{}-{}", process::id(), name));
+        fs::create_dir_all(&path).unwrap();
+        Scratch(path)
+    }
+
+    pub fn path(&self, name: &str) -> PathBuf {
+        self.0.join(name)
+    }
+}
+
+impl AsRef<Path> for Scratch {
+    fn as_ref(&self) -> &Path {
+        &self.0
+    }
+}
+
+impl Drop for Scratch {
+    fn drop(&mut self) {
+        let _ = fs::remove_dir_all(&self.0);
+    }
+}
+
+// A process running in the background until dropped, started once it printed its first line
+pub struct Daemon {
+    child: Child,
+    pub first_line: String,
+}
+
+impl Daemon {
+    pub fn spawn<S: AsRef<OsStr>>(program: &str, args: &[S]) -> Daemon {
+        let mut child = Command::new(program)
+            .args(args)
+            .stdout(Stdio::piped())
+            .spawn()
+            .unwrap();
+        let mut stdout = BufReader::new(child.stdout.take().unwrap());
+        let mut first_line = String::new();
+        stdout.read_line(&mut first_line).unwrap();
+        // keep draining so the process never blocks on a full pipe
+        thread::spawn(move || stdout.read_to_end(&mut Vec::new()));
+        Daemon { child, first_line }
+    }
+
+    // The number a car ends its first line with: its UDP port or its address on the air
+    pub fn address(&self) -> u16 {
+        self.first_line
+            .trim()
+            .rsplit(' ')
+            .next()
+            .and_then(|address| address.parse().ok())
+            .unwrap_or_else(|| panic!("started with {:?}", self.first_line))
+    }
+}
+
+impl Drop for Daemon {
+    fn drop(&mut self) {
+        let _ = self.child.kill();
+        let _ = self.child.wait();
+    }
+}
+
+pub fn car<S: AsRef<OsStr>>(keys: &Scratch, args: &[S]) -> Daemon {
+    let mut all: Vec<&OsStr> = vec!["--keys".as_ref(), keys.as_ref().as_os_str()];
+    all.extend(args.iter().map(|arg| arg.as_ref()));
+    Daemon::spawn(env!("CARGO_BIN_EXE_car"), &all)
+}
+
+pub fn keychain<S: AsRef<OsStr>>(keys: &Scratch, args: &[S]) -> Output {
+    Command::new(env!("CARGO_BIN_EXE_keychain"))
+        .arg("--keys")
+        .arg(keys.as_ref())
+        .args(args)
+        .output()
+        .unwrap()
+}
+
+// How many commands a keychain's output says were confirmed
+pub fn confirmed(output: &Output) -> usize {
+    String::from_utf8_lossy(&output.stdout)
+        .matches(" confirmed,")
+        .count()
+}
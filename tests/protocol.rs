@@ -0,0 +1,1490 @@
+extern crate keychain_protocol;
+
+use keychain_protocol::clock::{Clock, MockClock};
+use keychain_protocol::crypto::{Algorithm, Backend, KeyError};
+use keychain_protocol::fragment::{
+    Fragmented, Fragmenter, Reassembler, RADIO_MTU, REASSEMBLY_TIMEOUT,
+};
+use keychain_protocol::frame;
+use keychain_protocol::simulation::Simulation;
+use keychain_protocol::transport::{
+    serve, serve_all, Address, Channel, Ether, EtherTransport, Latency, LossyEther, Transport,
+};
+use keychain_protocol::{
+    make_key_car_pair, run, Car, CarState, Command, DenyReason, Event, FreshnessPolicy, Keychain,
+    LockState, Message, MessageKind, MessageProcessor, Mode, ProtocolError, RetryPolicy, Trigger,
+    AUTO_RELOCK, COUNTER_LOOK_AHEAD, MAX_FORWARD_SKEW, MAX_LEARNED_SKEW, RANGE_ROUNDS,
+};
+use std::cell::RefCell;
+use std::collections::VecDeque;
+use std::iter;
+use std::rc::Rc;
+use std::time::Duration;
+
+fn kind(message: &[u8]) -> MessageKind {
+    frame::decode(message).unwrap().kind
+}
+
+fn successes(log: &[Vec<u8>]) -> usize {
+    log.iter()
+        .filter(|m| kind(m) == MessageKind::Success)
+        .count()
+}
+
+// Re-encodes a frame after `change` had its way with the algorithm and the payload,
+// the way an attacker who understands the framing would
+fn tamper(message: &[u8], change: impl FnOnce(&mut u8, &mut Vec<u8>)) -> Vec<u8> {
+    let frame = frame::decode(message).unwrap();
+    let mut algorithm = frame.algorithm;
+    let mut payload = frame.payload.to_vec();
+    change(&mut algorithm, &mut payload);
+    frame::encode(frame.kind, algorithm, &payload)
+}
+
+// Order of the P-256 group, big-endian
+const P256_ORDER: [u8; 32] = [
+    0xff, 0xff, 0xff, 0xff, 0x00, 0x00, 0x00, 0x00, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff,
+    0xbc, 0xe6, 0xfa, 0xad, 0xa7, 0x17, 0x9e, 0x84, 0xf3, 0xb9, 0xca, 0xc2, 0xfc, 0x63, 0x25, 0x51,
+];
+
+// `message` with the `s` of its ECDSA P-256 signature replaced by `n - s`, another signature
+// just as valid over the same bytes
+fn malleated(message: &[u8]) -> Vec<u8> {
+    let decoded = frame::decode(message).unwrap();
+    let mut payload = decoded.payload.to_vec();
+    let s = payload.len() - P256_ORDER.len();
+    let mut borrow = 0;
+    for (byte, order) in payload[s..].iter_mut().zip(&P256_ORDER).rev() {
+        let difference = *order as i16 - *byte as i16 - borrow;
+        *byte = difference.rem_euclid(256) as u8;
+        borrow = (difference < 0) as i16;
+    }
+    frame::encode(decoded.kind, decoded.algorithm, &payload)
+}
+
+fn replayed_command_open_is_rejected<B: Backend>() {
+    let (mut car, mut keychain) =
+        make_key_car_pair::<B>(Mode::Timestamp, Algorithm::RsaPss).unwrap();
+    let message = keychain.get_initiation_message().unwrap();
+    let ether: VecDeque<Vec<u8>> = vec![message.clone(), message].into();
+    let log = run(&mut [&mut car, &mut keychain], ether);
+    assert_eq!(successes(&log), 1);
+
+    // a replay does not need the very same signature
+    let (mut car, mut keychain) =
+        make_key_car_pair::<B>(Mode::Timestamp, Algorithm::EcdsaP256).unwrap();
+    let message = keychain.get_initiation_message().unwrap();
+    let replay = malleated(&message);
+    assert_ne!(replay, message);
+    let ether: VecDeque<Vec<u8>> = vec![replay, message].into();
+    let log = run(&mut [&mut car, &mut keychain], ether);
+    assert_eq!(successes(&log), 1);
+}
+
+fn full_replay_cache_refuses_fresh_messages<B: Backend>() {
+    let (car, mut keychain) = make_key_car_pair::<B>(Mode::Timestamp, Algorithm::Ed25519).unwrap();
+    let mut car = car.with_replay_capacity(1);
+    let first = keychain.get_initiation_message().unwrap();
+    let second = keychain.get_initiation_message().unwrap();
+    let ether: VecDeque<Vec<u8>> = vec![second.clone(), first.clone()].into();
+    let log = run(&mut [&mut car, &mut keychain], ether);
+    assert_eq!(successes(&log), 1);
+    // the second is fresh and was never accepted, there is just no room to remember it
+    assert_eq!(car.process(&second), Err(ProtocolError::ReplayCacheFull));
+    assert_eq!(car.process(&first), Err(ProtocolError::Replay));
+}
+
+fn counter_resynchronizes_after_jump<B: Backend>() {
+    let (mut car, mut keychain) =
+        make_key_car_pair::<B>(Mode::Counter, Algorithm::Ed25519).unwrap();
+    let first = keychain.get_initiation_message().unwrap();
+    for _ in 0..COUNTER_LOOK_AHEAD {
+        keychain.get_initiation_message().unwrap(); // pressed out of range
+    }
+    let jumped = keychain.get_initiation_message().unwrap();
+    let next = keychain.get_initiation_message().unwrap();
+    let ether: VecDeque<Vec<u8>> = vec![next, jumped, first.clone(), first].into();
+    let log = run(&mut [&mut car, &mut keychain], ether);
+    // the replayed first frame is refused, the jump takes two frames to accept
+    assert_eq!(successes(&log), 2);
+    assert_eq!(car.counter(), COUNTER_LOOK_AHEAD + 3);
+}
+
+// A keychain and a car that lost power and were rebuilt from the counters they saved
+fn counters_survive_a_restart<B: Backend>() {
+    let (car_public, car_private) = B::generate(Algorithm::Ed25519).unwrap();
+    let (keychain_public, keychain_private) = B::generate(Algorithm::Ed25519).unwrap();
+    let car = || {
+        Car::<B>::new(
+            &car_private,
+            &keychain_public,
+            Mode::Counter,
+            Algorithm::Ed25519,
+        )
+    };
+    let keychain = || {
+        Keychain::<B>::new(
+            &keychain_private,
+            &car_public,
+            Mode::Counter,
+            Algorithm::Ed25519,
+        )
+    };
+
+    let (mut first_car, mut first_keychain) = (car().unwrap(), keychain().unwrap());
+    let first = first_keychain.get_initiation_message().unwrap();
+    let ether: VecDeque<Vec<u8>> = vec![first.clone()].into();
+    let log = run(&mut [&mut first_car, &mut first_keychain], ether);
+    assert_eq!(successes(&log), 1);
+    let saved = first_keychain.counter();
+    assert_eq!(saved, 1);
+
+    // a keychain starting over from zero repeats a counter the car has accepted
+    let mut forgetful = keychain().unwrap();
+    let ether: VecDeque<Vec<u8>> = vec![forgetful.get_initiation_message().unwrap()].into();
+    let log = run(&mut [&mut first_car, &mut forgetful], ether);
+    assert_eq!(successes(&log), 0);
+
+    let mut car = car().unwrap().with_counter(first_car.counter());
+    let mut keychain = keychain().unwrap().with_counter(saved);
+    let ether: VecDeque<Vec<u8>> = vec![keychain.get_initiation_message().unwrap()].into();
+    let log = run(&mut [&mut car, &mut keychain], ether);
+    assert_eq!(successes(&log), 1);
+    assert_eq!((car.counter(), keychain.counter()), (2, 2));
+    // the restored car still refuses what it accepted before
+    assert_eq!(car.process(&first), Err(ProtocolError::Replay));
+}
+
+fn legacy_pkcs1_signatures_are_accepted<B: Backend>() {
+    let (mut car, mut keychain) =
+        make_key_car_pair::<B>(Mode::Timestamp, Algorithm::RsaPkcs1v15).unwrap();
+    let ether: VecDeque<Vec<u8>> = vec![keychain.get_initiation_message().unwrap()].into();
+    let log = run(&mut [&mut car, &mut keychain], ether);
+    assert_eq!(successes(&log), 1);
+}
+
+fn short_algorithms_fit_radio_frames<B: Backend>() {
+    for &algorithm in &[Algorithm::Ed25519, Algorithm::EcdsaP256] {
+        for &mode in &[Mode::Timestamp, Mode::Challenge, Mode::Counter] {
+            let (mut car, mut keychain) = make_key_car_pair::<B>(mode, algorithm).unwrap();
+            let mut ether: VecDeque<Vec<u8>> =
+                vec![keychain.get_initiation_message().unwrap()].into();
+            if mode == Mode::Timestamp {
+                ether.push_front(keychain.sync_time().unwrap());
+            }
+            let log = run(&mut [&mut car, &mut keychain], ether);
+            assert_eq!(successes(&log), 1);
+            assert!(log.iter().all(|m| m.len() < 80));
+        }
+    }
+}
+
+fn devices_talk_over_a_transport<B: Backend>() {
+    let (mut car, mut keychain) =
+        make_key_car_pair::<B>(Mode::Challenge, Algorithm::Ed25519).unwrap();
+    let ether = Ether::new();
+    let (mut car_radio, mut keychain_radio) = (ether.connect(), ether.connect());
+    let hello = keychain.get_initiation_message().unwrap();
+    keychain_radio.send(Address::BROADCAST, &hello).unwrap();
+    serve_all(&mut [
+        (&mut car, &mut car_radio),
+        (&mut keychain, &mut keychain_radio),
+    ])
+    .unwrap();
+    assert!(keychain.confirmed());
+    let kinds: Vec<_> = ether.log().iter().map(|m| kind(m)).collect();
+    assert_eq!(
+        kinds,
+        vec![
+            MessageKind::Hello,
+            MessageKind::Challenge,
+            MessageKind::Command,
+            MessageKind::Success
+        ]
+    );
+}
+
+fn foreign_challenge_is_ignored<B: Backend>() {
+    let (mut car, mut keychain) =
+        make_key_car_pair::<B>(Mode::Challenge, Algorithm::Ed25519).unwrap();
+    let (mut other_car, _) = make_key_car_pair::<B>(Mode::Challenge, Algorithm::Ed25519).unwrap();
+    let hello = keychain.get_initiation_message().unwrap();
+    let foreign = other_car.process(&hello).unwrap().unwrap();
+    let challenge = car.process(&hello).unwrap().unwrap();
+    assert_eq!(keychain.process(&foreign), Err(ProtocolError::BadSignature));
+    let open = keychain.process(&challenge).unwrap().unwrap();
+    assert!(car.process(&open).unwrap().is_some());
+}
+
+fn frame_with_another_algorithm_is_rejected<B: Backend>() {
+    let (mut car, mut keychain) = make_key_car_pair::<B>(Mode::Counter, Algorithm::RsaPss).unwrap();
+    let message = tamper(
+        &keychain.get_initiation_message().unwrap(),
+        |algorithm, _| *algorithm = Algorithm::RsaPkcs1v15 as u8,
+    );
+    assert_eq!(car.process(&message), Err(ProtocolError::WrongAlgorithm));
+}
+
+fn refusals_carry_a_reason<B: Backend>() {
+    let (mut car, mut keychain) =
+        make_key_car_pair::<B>(Mode::Timestamp, Algorithm::Ed25519).unwrap();
+    assert_eq!(car.process(&[]), Err(ProtocolError::Malformed));
+    assert_eq!(
+        car.process(&frame::encode(
+            MessageKind::Command,
+            Algorithm::Ed25519 as u8,
+            &[]
+        )),
+        Err(ProtocolError::Malformed)
+    );
+
+    let message = keychain.get_initiation_message().unwrap();
+    let tampered = tamper(&message, |_, payload| *payload.last_mut().unwrap() ^= 1);
+    assert_eq!(car.process(&tampered), Err(ProtocolError::BadSignature));
+    assert!(car.process(&message).unwrap().is_some());
+    assert_eq!(car.process(&message), Err(ProtocolError::Replay));
+
+    let future = tamper(&message, |_, payload| payload[1] = 0xff);
+    assert_eq!(car.process(&future), Err(ProtocolError::BadSignature));
+
+    let (mut car, mut keychain) =
+        make_key_car_pair::<B>(Mode::Challenge, Algorithm::Ed25519).unwrap();
+    let hello = keychain.get_initiation_message().unwrap();
+    let challenge = car.process(&hello).unwrap().unwrap();
+    let open = keychain.process(&challenge).unwrap().unwrap();
+    assert!(car.process(&open).unwrap().is_some());
+    assert_eq!(car.process(&open), Err(ProtocolError::NoChallenge));
+}
+
+fn refusals_are_answered_on_the_wire<B: Backend>() {
+    let (mut car, mut keychain) =
+        make_key_car_pair::<B>(Mode::Timestamp, Algorithm::Ed25519).unwrap();
+    let message = keychain.get_initiation_message().unwrap();
+    assert!(car.process(&message).unwrap().is_some());
+    let error = car.process(&message).unwrap_err();
+    let denied = car.refusal(error).unwrap();
+    assert_eq!(kind(&denied), MessageKind::Denied);
+    assert_eq!(
+        keychain.process(&denied),
+        Err(ProtocolError::Denied(DenyReason::Rejected))
+    );
+
+    // strangers get no answer at all
+    let tampered = tamper(&keychain.get_initiation_message().unwrap(), |_, payload| {
+        *payload.last_mut().unwrap() ^= 1
+    });
+    let error = car.process(&tampered).unwrap_err();
+    assert_eq!(error, ProtocolError::BadSignature);
+    assert_eq!(car.refusal(error), None);
+
+    let (mut car, mut keychain) =
+        make_key_car_pair::<B>(Mode::Counter, Algorithm::Ed25519).unwrap();
+    for _ in 0..=COUNTER_LOOK_AHEAD {
+        keychain.get_initiation_message().unwrap();
+    }
+    let jumped = keychain.get_initiation_message().unwrap();
+    let error = car.process(&jumped).unwrap_err();
+    let resync = car.refusal(error).unwrap();
+    assert_eq!(kind(&resync), MessageKind::ResyncRequired);
+    assert_eq!(
+        keychain.process(&resync),
+        Err(ProtocolError::ResyncRequired)
+    );
+}
+
+fn success_is_authenticated<B: Backend>() {
+    let (mut car, mut keychain) =
+        make_key_car_pair::<B>(Mode::Counter, Algorithm::Ed25519).unwrap();
+    let (mut other_car, mut other_keychain) =
+        make_key_car_pair::<B>(Mode::Counter, Algorithm::Ed25519).unwrap();
+
+    // a bare Success as anyone in the ether could send it
+    keychain.get_initiation_message().unwrap();
+    assert_eq!(
+        keychain.process(&frame::encode(
+            MessageKind::Success,
+            Algorithm::Ed25519 as u8,
+            &[0]
+        )),
+        Err(ProtocolError::Malformed)
+    );
+
+    // a well-formed Success signed by some other car
+    let foreign = other_car
+        .process(&other_keychain.get_initiation_message().unwrap())
+        .unwrap()
+        .unwrap();
+    assert_eq!(keychain.process(&foreign), Err(ProtocolError::BadSignature));
+    assert!(!keychain.confirmed());
+
+    // our car's Success for an earlier command
+    let first = keychain.get_initiation_message().unwrap();
+    let old_success = car.process(&first).unwrap().unwrap();
+    let second = keychain.get_initiation_message().unwrap();
+    assert_eq!(
+        keychain.process(&old_success),
+        Err(ProtocolError::BadSignature)
+    );
+    assert!(!keychain.confirmed());
+
+    let success = car.process(&second).unwrap().unwrap();
+    assert_eq!(keychain.process(&success), Ok(None));
+    assert!(keychain.confirmed());
+}
+
+fn frames_are_parsed_strictly<B: Backend>() {
+    let (mut car, mut keychain) =
+        make_key_car_pair::<B>(Mode::Counter, Algorithm::Ed25519).unwrap();
+    let message = keychain.get_initiation_message().unwrap();
+    let with_checksum = |mut bytes: Vec<u8>| {
+        let crc = frame::checksum(&bytes);
+        bytes.extend_from_slice(&crc.to_be_bytes());
+        bytes
+    };
+    let unframed = &message[..message.len() - frame::CHECKSUM_LENGTH];
+
+    let mut damaged = message.clone();
+    damaged[5] ^= 1;
+    assert_eq!(car.process(&damaged), Err(ProtocolError::Checksum));
+
+    let mut future = unframed.to_vec();
+    future[0] = frame::PROTOCOL_VERSION + 1;
+    assert_eq!(
+        car.process(&with_checksum(future)),
+        Err(ProtocolError::UnknownVersion(frame::PROTOCOL_VERSION + 1))
+    );
+
+    let mut unknown = unframed.to_vec();
+    unknown[1] = 0xff;
+    assert_eq!(
+        car.process(&with_checksum(unknown)),
+        Err(ProtocolError::UnknownKind)
+    );
+
+    // trailing bytes, a truncated payload, a length that is not minimal
+    let mut longer = unframed.to_vec();
+    longer.push(0);
+    assert_eq!(
+        car.process(&with_checksum(longer)),
+        Err(ProtocolError::Malformed)
+    );
+    let shorter = unframed[..unframed.len() - 1].to_vec();
+    assert_eq!(
+        car.process(&with_checksum(shorter)),
+        Err(ProtocolError::Malformed)
+    );
+    let frame = frame::decode(&message).unwrap();
+    let mut padded = frame.header().to_vec();
+    padded.extend_from_slice(&(frame.payload.len() as u16 | 0x8000).to_be_bytes());
+    padded.extend_from_slice(frame.payload);
+    assert_eq!(
+        car.process(&with_checksum(padded)),
+        Err(ProtocolError::Malformed)
+    );
+
+    // nothing was consumed by the refused frames
+    assert!(car.process(&message).unwrap().is_some());
+}
+
+fn messages_borrow_from_the_frame<B: Backend>() {
+    let (mut car, mut keychain) =
+        make_key_car_pair::<B>(Mode::Counter, Algorithm::Ed25519).unwrap();
+    let within = |part: &[u8], bytes: &[u8]| bytes.as_ptr_range().contains(&part.as_ptr());
+    let message = keychain.command(Command::Lock).unwrap();
+    let request = match Message::parse(&message, Mode::Counter).unwrap() {
+        Message::Command(request) => request,
+        other => panic!("parsed as {:?}", other.kind()),
+    };
+    assert_eq!(request.command, Command::Lock);
+    assert_eq!(request.token, &1u64.to_be_bytes()[..]);
+    assert!(within(request.token, &message));
+    assert!(within(request.signature.bytes, &message));
+    assert_eq!(request.signature.covers.as_ptr(), message.as_ptr());
+
+    let success = car.handle(&Message::Command(request)).unwrap().unwrap();
+    match Message::parse(&success, Mode::Counter).unwrap() {
+        Message::Success(reply) => assert_eq!(reply.state, car.state()),
+        other => panic!("parsed as {:?}", other.kind()),
+    }
+    assert_eq!(keychain.process(&success), Ok(None));
+    assert!(keychain.confirmed());
+
+    // a command byte outside the enum is refused before any signature is checked
+    let unknown = tamper(&message, |_, payload| payload[0] = 0xff);
+    assert_eq!(
+        Message::parse(&unknown, Mode::Counter),
+        Err(ProtocolError::Malformed)
+    );
+}
+
+fn broken_key_is_reported<B: Backend>() {
+    let (public_pem, private_pem) = B::generate(Algorithm::Ed25519).unwrap();
+    let car = Car::<B>::new(
+        &private_pem,
+        b"not a pem",
+        Mode::Counter,
+        Algorithm::Ed25519,
+    );
+    assert_eq!(car.err(), Some(ProtocolError::KeyLoad(KeyError)));
+    let keychain = Keychain::<B>::new(b"not a pem", &public_pem, Mode::Counter, Algorithm::Ed25519);
+    assert_eq!(keychain.err(), Some(ProtocolError::KeyLoad(KeyError)));
+}
+
+fn keys_of_another_algorithm_are_refused<B: Backend>() {
+    let (public_pem, private_pem) = B::generate(Algorithm::Ed25519).unwrap();
+    let (p256_public, _) = B::generate(Algorithm::EcdsaP256).unwrap();
+    for &algorithm in &[
+        Algorithm::RsaPss,
+        Algorithm::RsaPkcs1v15,
+        Algorithm::EcdsaP256,
+    ] {
+        let car = Car::<B>::new(&private_pem, &public_pem, Mode::Counter, algorithm);
+        assert_eq!(car.err(), Some(ProtocolError::KeyLoad(KeyError)));
+        let keychain = Keychain::<B>::new(&private_pem, &public_pem, Mode::Counter, algorithm);
+        assert_eq!(keychain.err(), Some(ProtocolError::KeyLoad(KeyError)));
+    }
+    // a car given the wrong kind of public key for its keychain finds out before any frame
+    let car = Car::<B>::new(
+        &private_pem,
+        &p256_public,
+        Mode::Counter,
+        Algorithm::Ed25519,
+    );
+    assert_eq!(car.err(), Some(ProtocolError::KeyLoad(KeyError)));
+}
+
+fn commands_drive_car_state<B: Backend>() {
+    let (mut car, mut keychain) =
+        make_key_car_pair::<B>(Mode::Challenge, Algorithm::Ed25519).unwrap();
+    let mut send = |command| {
+        let ether: VecDeque<Vec<u8>> = vec![keychain.command(command).unwrap()].into();
+        let log = run(&mut [&mut car, &mut keychain], ether);
+        assert_eq!(successes(&log), 1, "{:?}", command);
+        keychain.car_state().unwrap()
+    };
+    let state = send(Command::Open);
+    assert_eq!(state.lock, LockState::Unlocked);
+    let state = send(Command::Lock);
+    assert_eq!(state.lock, LockState::Armed);
+    let state = send(Command::EngineStart);
+    assert!(state.engine_running);
+    assert_eq!(state.lock, LockState::Locked);
+    let state = send(Command::Panic);
+    assert_eq!(state.lock, LockState::Alarm);
+    for &command in &[Command::TrunkRelease, Command::WindowClose, Command::Locate] {
+        send(command);
+    }
+    let state = send(Command::EngineStop);
+    assert_eq!(
+        state,
+        CarState {
+            lock: LockState::Alarm,
+            engine_running: false,
+        }
+    );
+    let state = send(Command::Panic);
+    assert_eq!(state.lock, LockState::Armed);
+    let state = send(Command::Open);
+    assert_eq!(state.lock, LockState::Unlocked);
+    assert_eq!(car.state(), state);
+}
+
+fn commands_are_checked_against_state<B: Backend>() {
+    let (mut car, mut keychain) =
+        make_key_car_pair::<B>(Mode::Counter, Algorithm::EcdsaP256).unwrap();
+    let stop = keychain.command(Command::EngineStop).unwrap();
+    let error = car.process(&stop).unwrap_err();
+    assert_eq!(error, ProtocolError::Unavailable);
+    let denied = car.refusal(error).unwrap();
+    assert_eq!(
+        keychain.process(&denied),
+        Err(ProtocolError::Denied(DenyReason::Unavailable))
+    );
+
+    // remote start is only allowed while the car is locked
+    car.process(&keychain.command(Command::Open).unwrap())
+        .unwrap();
+    let start = keychain.command(Command::EngineStart).unwrap();
+    assert_eq!(car.process(&start), Err(ProtocolError::Unavailable));
+    assert!(!car.state().engine_running);
+}
+
+fn commands_need_permission<B: Backend>() {
+    let (car, mut keychain) = make_key_car_pair::<B>(Mode::Counter, Algorithm::Ed25519).unwrap();
+    let mut car = car.with_permissions(&[Command::Open, Command::Lock, Command::Locate]);
+    assert!(car
+        .process(&keychain.command(Command::Lock).unwrap())
+        .is_ok());
+    let start = keychain.command(Command::EngineStart).unwrap();
+    let error = car.process(&start).unwrap_err();
+    assert_eq!(error, ProtocolError::NotPermitted);
+    let denied = car.refusal(error).unwrap();
+    assert_eq!(
+        keychain.process(&denied),
+        Err(ProtocolError::Denied(DenyReason::Rejected))
+    );
+    assert!(!car.state().engine_running);
+}
+
+fn reply_for_another_command_is_rejected<B: Backend>() {
+    let (mut car, mut keychain) =
+        make_key_car_pair::<B>(Mode::Timestamp, Algorithm::Ed25519).unwrap();
+    let lock = keychain.command(Command::Lock).unwrap();
+    let success = car.process(&lock).unwrap().unwrap();
+    keychain.command(Command::Open).unwrap();
+    // an attacker cannot pass the Success of a Lock off as the answer to an Open
+    assert_eq!(keychain.process(&success), Err(ProtocolError::BadSignature));
+    assert!(!keychain.confirmed());
+}
+
+fn car_relocks_when_no_door_opens<B: Backend>() {
+    let clock = MockClock::new();
+    let (car, mut keychain) = make_key_car_pair::<B>(Mode::Counter, Algorithm::Ed25519).unwrap();
+    let mut car = car
+        .with_clock(Box::new(clock.clone()))
+        .with_auto_relock(Some(Duration::from_secs(10)));
+    car.process(&keychain.command(Command::Open).unwrap())
+        .unwrap();
+    clock.advance(Duration::from_millis(9999));
+    car.poll();
+    assert_eq!(car.state().lock, LockState::Unlocked);
+    clock.advance(Duration::from_millis(1));
+    car.poll();
+    assert_eq!(car.state().lock, LockState::Armed);
+
+    // a door opened in time keeps the car unlocked
+    car.process(&keychain.command(Command::Open).unwrap())
+        .unwrap();
+    clock.advance(Duration::from_secs(5));
+    car.door_opened();
+    clock.advance(Duration::from_secs(60));
+    car.poll();
+    assert_eq!(car.state().lock, LockState::Unlocked);
+
+    // silencing the alarm back to unlocked starts the timer again, Panic twice cannot dodge it
+    for _ in 0..2 {
+        car.process(&keychain.command(Command::Panic).unwrap())
+            .unwrap();
+    }
+    assert_eq!(car.state().lock, LockState::Unlocked);
+    clock.advance(Duration::from_secs(10));
+    car.poll();
+    assert_eq!(car.state().lock, LockState::Armed);
+
+    // the timer runs out between frames, the next command sees the relocked car
+    let (car, mut keychain) = make_key_car_pair::<B>(Mode::Counter, Algorithm::Ed25519).unwrap();
+    let mut car = car
+        .with_clock(Box::new(clock.clone()))
+        .with_auto_relock(Some(Duration::from_secs(10)));
+    car.process(&keychain.command(Command::Open).unwrap())
+        .unwrap();
+    clock.advance(Duration::from_secs(11));
+    let start = keychain.command(Command::EngineStart).unwrap();
+    assert!(car.process(&start).unwrap().is_some());
+    assert_eq!(
+        car.state(),
+        CarState {
+            lock: LockState::Locked,
+            engine_running: true,
+        }
+    );
+}
+
+fn transitions_are_reported<B: Backend>() {
+    let clock = MockClock::new();
+    let events = Rc::new(RefCell::new(Vec::new()));
+    let (car, mut keychain) = make_key_car_pair::<B>(Mode::Counter, Algorithm::Ed25519).unwrap();
+    let log = events.clone();
+    let mut car = car
+        .with_clock(Box::new(clock.clone()))
+        .with_listener(Box::new(move |event| log.borrow_mut().push(*event)));
+    let armed = car.state();
+    assert_eq!(armed.lock, LockState::Armed);
+    let unlocked = CarState {
+        lock: LockState::Unlocked,
+        ..armed
+    };
+    let alarm = CarState {
+        lock: LockState::Alarm,
+        ..armed
+    };
+
+    car.process(&keychain.command(Command::Open).unwrap())
+        .unwrap();
+    car.process(&keychain.command(Command::Locate).unwrap())
+        .unwrap();
+    clock.advance(AUTO_RELOCK);
+    car.poll();
+    car.door_opened();
+    car.process(&keychain.command(Command::Lock).unwrap())
+        .unwrap();
+    assert_eq!(
+        *events.borrow(),
+        vec![
+            Event::Transition {
+                from: armed,
+                to: unlocked,
+                trigger: Trigger::Command(Command::Open),
+            },
+            Event::Pulse(Command::Locate),
+            Event::Transition {
+                from: unlocked,
+                to: armed,
+                trigger: Trigger::AutoRelock,
+            },
+            Event::Transition {
+                from: armed,
+                to: alarm,
+                trigger: Trigger::Door,
+            },
+            Event::Transition {
+                from: alarm,
+                to: armed,
+                trigger: Trigger::Command(Command::Lock),
+            },
+        ]
+    );
+}
+
+// A pair sharing one mock clock, set to some realistic wall clock time
+fn mock_pair<B: Backend>(mode: Mode) -> (Car<B>, Keychain<B>, MockClock) {
+    let clock = MockClock::new();
+    clock.advance(Duration::from_secs(1_600_000_000));
+    let (car, keychain) = make_key_car_pair::<B>(mode, Algorithm::Ed25519).unwrap();
+    (
+        car.with_clock(Box::new(clock.clone())),
+        keychain.with_clock(Box::new(clock.clone())),
+        clock,
+    )
+}
+
+// A timestamp pair where car and keychain each have their own mock clock
+fn skewed_pair<B: Backend>(
+    car_clock: &MockClock,
+    keychain_clock: &MockClock,
+) -> (Car<B>, Keychain<B>) {
+    let (car, keychain) = make_key_car_pair::<B>(Mode::Timestamp, Algorithm::Ed25519).unwrap();
+    (
+        car.with_clock(Box::new(car_clock.clone())),
+        keychain.with_clock(Box::new(keychain_clock.clone())),
+    )
+}
+
+fn timestamp_freshness_follows_the_clock<B: Backend>() {
+    let (mut car, mut keychain, clock) = mock_pair::<B>(Mode::Timestamp);
+    let message = keychain.get_initiation_message().unwrap();
+    clock.advance(Duration::from_millis(999));
+    assert!(car.process(&message).unwrap().is_some());
+
+    let (mut car, mut keychain, clock) = mock_pair::<B>(Mode::Timestamp);
+    let message = keychain.get_initiation_message().unwrap();
+    clock.advance(Duration::from_millis(1001));
+    assert_eq!(car.process(&message), Err(ProtocolError::Stale));
+
+    // a keychain whose clock runs ahead of the car's by more than the policy tolerates
+    let ahead = MockClock::new();
+    ahead.advance(clock.now() + MAX_FORWARD_SKEW + Duration::from_millis(1));
+    let (mut car, mut keychain) = skewed_pair::<B>(&clock, &ahead);
+    let message = keychain.get_initiation_message().unwrap();
+    assert_eq!(car.process(&message), Err(ProtocolError::FromFuture));
+}
+
+fn forward_skew_is_tolerated<B: Backend>() {
+    let car_clock = MockClock::new();
+    let keychain_clock = MockClock::new();
+    keychain_clock.advance(MAX_FORWARD_SKEW);
+    let (mut car, mut keychain) = skewed_pair::<B>(&car_clock, &keychain_clock);
+    assert!(car
+        .process(&keychain.get_initiation_message().unwrap())
+        .unwrap()
+        .is_some());
+
+    let (car, mut keychain) = skewed_pair::<B>(&car_clock, &keychain_clock);
+    let mut car = car.with_policy(FreshnessPolicy {
+        max_forward_skew: Duration::from_millis(100),
+        ..FreshnessPolicy::default()
+    });
+    let message = keychain.get_initiation_message().unwrap();
+    assert_eq!(car.process(&message), Err(ProtocolError::FromFuture));
+    assert_eq!(
+        car.diagnostics().observed_skew,
+        Some(MAX_FORWARD_SKEW.as_nanos() as i64)
+    );
+
+    let (car, mut keychain, clock) = mock_pair::<B>(Mode::Timestamp);
+    let mut car = car.with_policy(FreshnessPolicy {
+        max_age: Duration::from_secs(5),
+        ..FreshnessPolicy::default()
+    });
+    let message = keychain.get_initiation_message().unwrap();
+    clock.advance(Duration::from_millis(4999));
+    assert!(car.process(&message).unwrap().is_some());
+}
+
+fn drifting_keychain_is_followed<B: Backend>() {
+    let car_clock = MockClock::new();
+    let keychain_clock = MockClock::new();
+    let (mut car, mut keychain) = skewed_pair::<B>(&car_clock, &keychain_clock);
+    let (strict_car, mut strict_keychain) = skewed_pair::<B>(&car_clock, &keychain_clock);
+    let mut strict_car = strict_car.with_policy(FreshnessPolicy {
+        max_learned_skew: Duration::from_secs(0),
+        ..FreshnessPolicy::default()
+    });
+    let mut strict_failures = 0;
+    // the fob gains 50 ms between presses a minute apart, 4 s over the whole run
+    for _ in 0..80 {
+        car_clock.advance(Duration::from_secs(60));
+        keychain_clock.advance(Duration::from_secs(60) + Duration::from_millis(50));
+        let message = keychain.get_initiation_message().unwrap();
+        assert!(car.process(&message).unwrap().is_some());
+        let message = strict_keychain.get_initiation_message().unwrap();
+        if strict_car.process(&message).is_err() {
+            strict_failures += 1;
+        }
+    }
+    assert!(strict_failures > 70);
+
+    let diagnostics = car.diagnostics();
+    assert_eq!(diagnostics.observed_skew, Some(4_000_000_000));
+    let lag = diagnostics.observed_skew.unwrap() - diagnostics.learned_skew;
+    assert!(lag >= 0 && lag < MAX_FORWARD_SKEW.as_nanos() as i64);
+
+    // compensation stops at the policy's bound
+    let error = loop {
+        car_clock.advance(Duration::from_secs(60));
+        keychain_clock.advance(Duration::from_secs(60) + Duration::from_millis(50));
+        if let Err(e) = car.process(&keychain.get_initiation_message().unwrap()) {
+            break e;
+        }
+    };
+    assert_eq!(error, ProtocolError::FromFuture);
+    assert_eq!(
+        car.diagnostics().learned_skew,
+        MAX_LEARNED_SKEW.as_nanos() as i64
+    );
+    assert!(
+        car.diagnostics().observed_skew.unwrap()
+            > (MAX_LEARNED_SKEW + MAX_FORWARD_SKEW).as_nanos() as i64
+    );
+}
+
+fn time_sync_corrects_a_drifting_keychain<B: Backend>() {
+    let car_clock = MockClock::new();
+    car_clock.advance(Duration::from_secs(1_600_000_000));
+    let keychain_clock = MockClock::new();
+    keychain_clock.advance(Duration::from_secs(1_600_000_000 - 5 * 60)); // five minutes behind
+    let (mut car, mut keychain) = skewed_pair::<B>(&car_clock, &keychain_clock);
+
+    let error = car
+        .process(&keychain.get_initiation_message().unwrap())
+        .unwrap_err();
+    assert_eq!(error, ProtocolError::Stale);
+    let denied = car.refusal(error).unwrap();
+    assert_eq!(
+        keychain.process(&denied),
+        Err(ProtocolError::Denied(DenyReason::Expired))
+    );
+
+    let ether: VecDeque<Vec<u8>> = vec![keychain.sync_time().unwrap()].into();
+    run(&mut [&mut car, &mut keychain], ether);
+    assert_eq!(keychain.clock_offset(), 5 * 60 * 1_000_000_000);
+    let ether: VecDeque<Vec<u8>> = vec![keychain.get_initiation_message().unwrap()].into();
+    assert_eq!(successes(&run(&mut [&mut car, &mut keychain], ether)), 1);
+
+    // a day later the fob has run three minutes fast
+    car_clock.advance(Duration::from_secs(24 * 60 * 60));
+    keychain_clock.advance(Duration::from_secs(24 * 60 * 60 + 3 * 60));
+    let message = keychain.get_initiation_message().unwrap();
+    assert_eq!(car.process(&message), Err(ProtocolError::FromFuture));
+    let ether: VecDeque<Vec<u8>> = vec![keychain.sync_time().unwrap()].into();
+    run(&mut [&mut car, &mut keychain], ether);
+    assert_eq!(keychain.clock_offset(), 2 * 60 * 1_000_000_000);
+    let ether: VecDeque<Vec<u8>> = vec![keychain.get_initiation_message().unwrap()].into();
+    assert_eq!(successes(&run(&mut [&mut car, &mut keychain], ether)), 1);
+}
+
+fn time_sync_is_authenticated<B: Backend>() {
+    let clock = MockClock::new();
+    let (mut car, mut keychain) = skewed_pair::<B>(&clock, &clock);
+    let (mut other_car, mut other_keychain) = skewed_pair::<B>(&clock, &clock);
+
+    // every request is answered once, later copies are not for us
+    let request = keychain.sync_time().unwrap();
+    let sync = car.process(&request).unwrap().unwrap();
+    assert_eq!(keychain.process(&sync), Ok(None));
+    clock.advance(Duration::from_secs(60));
+    assert_eq!(keychain.process(&sync), Ok(None));
+    assert_eq!(keychain.clock_offset(), 0);
+
+    // an answer to an earlier request, or from another car
+    let request = keychain.sync_time().unwrap();
+    clock.advance(Duration::from_secs(60));
+    let old_sync = car.process(&request).unwrap().unwrap();
+    keychain.sync_time().unwrap();
+    assert_eq!(
+        keychain.process(&old_sync),
+        Err(ProtocolError::BadSignature)
+    );
+    let foreign = other_car
+        .process(&other_keychain.sync_time().unwrap())
+        .unwrap()
+        .unwrap();
+    assert_eq!(keychain.process(&foreign), Err(ProtocolError::BadSignature));
+    assert_eq!(keychain.clock_offset(), 0);
+
+    // the car does not tell its time to strangers
+    let tampered = tamper(&keychain.sync_time().unwrap(), |_, payload| {
+        *payload.last_mut().unwrap() ^= 1
+    });
+    assert_eq!(car.process(&tampered), Err(ProtocolError::BadSignature));
+    assert_eq!(
+        car.process(&other_keychain.sync_time().unwrap()),
+        Err(ProtocolError::BadSignature)
+    );
+}
+
+fn challenge_expires_with_the_clock<B: Backend>() {
+    let (mut car, mut keychain, clock) = mock_pair::<B>(Mode::Challenge);
+    let challenge = car
+        .process(&keychain.get_initiation_message().unwrap())
+        .unwrap()
+        .unwrap();
+    let message = keychain.process(&challenge).unwrap().unwrap();
+    clock.advance(Duration::from_millis(999));
+    assert!(car.process(&message).unwrap().is_some());
+
+    let challenge = car
+        .process(&keychain.get_initiation_message().unwrap())
+        .unwrap()
+        .unwrap();
+    let message = keychain.process(&challenge).unwrap().unwrap();
+    clock.advance(Duration::from_millis(1001));
+    assert_eq!(car.process(&message), Err(ProtocolError::Stale));
+}
+
+fn replay_cache_frees_up_as_the_clock_moves<B: Backend>() {
+    let (car, mut keychain, clock) = mock_pair::<B>(Mode::Timestamp);
+    let mut car = car.with_replay_capacity(1);
+    let first = keychain.get_initiation_message().unwrap();
+    assert!(car.process(&first).unwrap().is_some());
+    clock.advance(Duration::from_millis(999));
+    let second = keychain.get_initiation_message().unwrap();
+    assert_eq!(car.process(&second), Err(ProtocolError::ReplayCacheFull));
+    clock.advance(Duration::from_millis(1));
+    let third = keychain.get_initiation_message().unwrap();
+    assert!(car.process(&third).unwrap().is_some());
+    assert_eq!(car.process(&third), Err(ProtocolError::Replay));
+}
+
+// One side of a fragmenting link: a device, its link to an ether of its own, and a radio on
+// that ether that hears and transmits single fragments
+type Side<D> = (D, Fragmented<EtherTransport>, EtherTransport);
+
+// An RSA pair, whose Command frames are several radio packets long, each behind a
+// fragmenting link. Fragments only pass from one side to the other as the test carries them.
+fn fragmented_pair<B: Backend>() -> (Side<Car<B>>, Side<Keychain<B>>, MockClock) {
+    let clock = MockClock::new();
+    let (car, keychain) = make_key_car_pair::<B>(Mode::Counter, Algorithm::RsaPss).unwrap();
+    let side = |first_id| {
+        let ether = Ether::new();
+        let link = Fragmented::new(ether.connect(), RADIO_MTU, first_id)
+            .with_clock(Box::new(clock.clone()));
+        (link, ether.connect())
+    };
+    let ((car_link, car_radio), (keychain_link, keychain_radio)) = (side(0x1000), side(0x2000));
+    (
+        (car, car_link, car_radio),
+        (keychain, keychain_link, keychain_radio),
+        clock,
+    )
+}
+
+// Every fragment waiting for `radio`
+fn heard(radio: &mut EtherTransport) -> Vec<Vec<u8>> {
+    iter::from_fn(|| radio.receive().unwrap())
+        .map(|(_, fragment)| fragment)
+        .collect()
+}
+
+fn fragments_survive_reordering_and_duplicates<B: Backend>() {
+    let ((mut car, mut car_link, mut car_radio), keychain_side, _) = fragmented_pair::<B>();
+    let (mut keychain, mut keychain_link, mut keychain_radio) = keychain_side;
+    let open = keychain.command(Command::Open).unwrap();
+    assert!(open.len() > RADIO_MTU);
+    keychain_link.send(Address::BROADCAST, &open).unwrap();
+    let mut fragments = heard(&mut keychain_radio);
+    assert!(fragments.len() > 1);
+    assert!(fragments.iter().all(|f| f.len() <= RADIO_MTU));
+
+    fragments.reverse();
+    fragments.insert(2, fragments[0].clone());
+    fragments.push(fragments[1].clone()); // arrives after the frame is complete
+    for fragment in &fragments {
+        car_radio.send(Address::BROADCAST, fragment).unwrap();
+    }
+    assert_eq!(serve(&mut car, &mut car_link).unwrap(), 1);
+    assert_eq!(car.state().lock, LockState::Unlocked);
+
+    let mut replies = heard(&mut car_radio);
+    replies.swap(0, 1);
+    for fragment in &replies {
+        keychain_radio.send(Address::BROADCAST, fragment).unwrap();
+    }
+    assert_eq!(serve(&mut keychain, &mut keychain_link).unwrap(), 1);
+    assert!(keychain.confirmed());
+}
+
+fn lost_fragments_time_out<B: Backend>() {
+    let ((mut car, mut car_link, mut car_radio), keychain_side, clock) = fragmented_pair::<B>();
+    let (mut keychain, mut keychain_link, mut keychain_radio) = keychain_side;
+    let open = keychain.command(Command::Open).unwrap();
+    keychain_link.send(Address::BROADCAST, &open).unwrap();
+    let fragments = heard(&mut keychain_radio);
+    let (last, rest) = fragments.split_last().unwrap();
+    for fragment in rest {
+        car_radio.send(Address::BROADCAST, fragment).unwrap();
+    }
+    assert_eq!(serve(&mut car, &mut car_link).unwrap(), 0);
+    clock.advance(REASSEMBLY_TIMEOUT);
+    // the rest of its frame was dropped in the meantime
+    car_radio.send(Address::BROADCAST, last).unwrap();
+    assert_eq!(serve(&mut car, &mut car_link).unwrap(), 0);
+    assert_eq!(car.state().lock, LockState::Armed);
+
+    // the keychain sends the frame again under a new id
+    keychain_link.send(Address::BROADCAST, &open).unwrap();
+    for fragment in heard(&mut keychain_radio) {
+        car_radio.send(Address::BROADCAST, &fragment).unwrap();
+    }
+    assert_eq!(serve(&mut car, &mut car_link).unwrap(), 1);
+    assert!(!heard(&mut car_radio).is_empty());
+    assert_eq!(car.state().lock, LockState::Unlocked);
+
+    // broken fragments are lost on the way like damaged packets
+    let broken: [&[u8]; 2] = [&[0x10, 0, 0, 1], &[0x10, 0, 2, 2, 0]];
+    let mut reassembler = Reassembler::new(REASSEMBLY_TIMEOUT);
+    for fragment in &broken {
+        assert_eq!(
+            reassembler.receive(car_radio.address(), fragment, clock.now()),
+            Err(ProtocolError::Malformed)
+        );
+        car_radio.send(Address::BROADCAST, fragment).unwrap();
+    }
+    assert_eq!(serve(&mut car, &mut car_link).unwrap(), 0);
+}
+
+fn keychain_presses_again_until_confirmed<B: Backend>() {
+    let (mut car, keychain, clock) = mock_pair::<B>(Mode::Counter);
+    let retry = RetryPolicy {
+        attempts: 2,
+        timeout: Duration::from_millis(100),
+    };
+    let mut keychain = keychain.with_retry(retry);
+    keychain.command(Command::Open).unwrap(); // lost on the way
+    clock.advance(Duration::from_millis(99));
+    assert_eq!(keychain.poll(), Ok(None));
+    clock.advance(Duration::from_millis(1));
+    let again = keychain.poll().unwrap().unwrap();
+    let success = car.process(&again).unwrap().unwrap();
+    assert_eq!(car.counter(), 2);
+    keychain.process(&success).unwrap();
+    assert!(keychain.confirmed());
+    assert!(!keychain.waiting());
+    assert_eq!(keychain.attempts(), 2);
+
+    // a refusal the radio cannot be blamed for ends the command at once
+    let stop = keychain.command(Command::EngineStop).unwrap();
+    let denied = car.process(&stop).map_err(|e| car.refusal(e).unwrap());
+    assert_eq!(
+        keychain.process(&denied.unwrap_err()),
+        Err(ProtocolError::Denied(DenyReason::Unavailable))
+    );
+    assert!(!keychain.waiting());
+
+    // otherwise the keychain gives up after its last attempt
+    keychain.command(Command::Lock).unwrap();
+    clock.advance(retry.timeout);
+    assert!(keychain.poll().unwrap().is_some());
+    clock.advance(retry.timeout);
+    assert_eq!(keychain.poll(), Ok(None));
+    assert!(!keychain.waiting());
+    assert!(!keychain.confirmed());
+}
+
+fn late_success_does_not_confirm_a_new_press<B: Backend>() {
+    let (mut car, mut keychain, _) = mock_pair::<B>(Mode::Challenge);
+    let hello = keychain.command(Command::Open).unwrap();
+    let challenge = car.process(&hello).unwrap().unwrap();
+    let open = keychain.process(&challenge).unwrap().unwrap();
+    let late = car.process(&open).unwrap().unwrap(); // held up on the way
+    assert_eq!(car.state().lock, LockState::Unlocked);
+
+    keychain.command(Command::Lock).unwrap();
+    assert_eq!(keychain.process(&late), Ok(None));
+    assert!(!keychain.confirmed());
+    assert!(keychain.waiting());
+}
+
+fn retries_overcome_a_lossy_channel<B: Backend>() {
+    let perfect = Simulation::new(Mode::Challenge, Algorithm::Ed25519, Channel::default())
+        .run::<B>(10)
+        .unwrap();
+    assert_eq!((perfect.confirmed, perfect.presses), (10, 10));
+    assert_eq!(perfect.latency(1.0), Some(Duration::ZERO));
+
+    let lossy = Channel {
+        loss: 0.2,
+        duplication: 0.1,
+        reordering: 0.1,
+        latency: Latency::Uniform(Duration::from_millis(1), Duration::from_millis(10)),
+        bit_error_rate: 1e-4,
+    };
+    let once = RetryPolicy {
+        attempts: 1,
+        ..RetryPolicy::default()
+    };
+    let simulation = Simulation::new(Mode::Counter, Algorithm::Ed25519, lossy).with_seed(7);
+    let without = simulation.with_retry(once).run::<B>(50).unwrap();
+    let with = simulation.run::<B>(50).unwrap();
+    assert_eq!(without.presses, 50);
+    assert!(with.confirmed > without.confirmed);
+    assert!(with.success_rate() > 0.9);
+    assert!(with.latency(0.5).unwrap() >= Duration::from_millis(2));
+    // the same seed loses the same frames
+    assert_eq!(simulation.run::<B>(50).unwrap(), with);
+}
+
+// Plays distance bounding between `car` and `keychain` from the car's first RangeChallenge,
+// each round trip taking `round_trip` or `slow` in round `slow_round`, returns the car's
+// answer to the proof
+fn bound_distance<B: Backend>(
+    car: &mut Car<B>,
+    keychain: &mut Keychain<B>,
+    clock: &MockClock,
+    mut frame: Vec<u8>,
+    round_trip: Duration,
+    (slow_round, slow): (usize, Duration),
+) -> Result<Option<Vec<u8>>, ProtocolError> {
+    for round in 0..=RANGE_ROUNDS {
+        assert_eq!(kind(&frame), MessageKind::RangeChallenge);
+        let response = keychain.process(&frame).unwrap().unwrap();
+        if round == RANGE_ROUNDS {
+            assert_eq!(kind(&response), MessageKind::RangeProof);
+            return car.process(&response);
+        }
+        assert_eq!(kind(&response), MessageKind::RangeResponse);
+        clock.advance(if round == slow_round {
+            slow
+        } else {
+            round_trip
+        });
+        frame = car.process(&response).unwrap().unwrap();
+    }
+    unreachable!()
+}
+
+fn distance_bounding_times_every_round<B: Backend>() {
+    let (car, mut keychain, clock) = mock_pair::<B>(Mode::Counter);
+    let max_round_trip = Duration::from_micros(100);
+    let mut car = car.with_distance_bounding(Some(max_round_trip));
+    let open = keychain.command(Command::Open).unwrap();
+    let first = car.process(&open).unwrap().unwrap();
+    assert_eq!(car.state().lock, LockState::Armed);
+    let success = bound_distance(
+        &mut car,
+        &mut keychain,
+        &clock,
+        first,
+        Duration::from_micros(50),
+        (0, max_round_trip),
+    )
+    .unwrap()
+    .unwrap();
+    assert_eq!(car.state().lock, LockState::Unlocked);
+    keychain.process(&success).unwrap();
+    assert!(keychain.confirmed());
+
+    // one slow round is enough to give a relay away
+    let lock = keychain.command(Command::Lock).unwrap();
+    let first = car.process(&lock).unwrap().unwrap();
+    let slow = Duration::from_micros(101);
+    let refused = bound_distance(
+        &mut car,
+        &mut keychain,
+        &clock,
+        first,
+        Duration::ZERO,
+        (7, slow),
+    );
+    assert_eq!(refused, Err(ProtocolError::OutOfRange));
+    assert_eq!(car.state().lock, LockState::Unlocked);
+    let denied = car.refusal(ProtocolError::OutOfRange).unwrap();
+    assert_eq!(
+        keychain.process(&denied),
+        Err(ProtocolError::Denied(DenyReason::Rejected))
+    );
+
+    // rounds out of turn are ignored, rounds beyond the last are malformed
+    let lock = keychain.command(Command::Lock).unwrap();
+    car.process(&lock).unwrap().unwrap();
+    let early = frame::encode(MessageKind::RangeResponse, frame::NO_ALGORITHM, &[1, 0]);
+    assert_eq!(car.process(&early), Ok(None));
+    let beyond = [RANGE_ROUNDS as u8 + 1, 0];
+    let beyond = frame::encode(MessageKind::RangeResponse, frame::NO_ALGORITHM, &beyond);
+    assert_eq!(car.process(&beyond), Err(ProtocolError::Malformed));
+}
+
+fn stale_commands_leave_ranging_alone<B: Backend>() {
+    let (car, mut keychain, clock) = mock_pair::<B>(Mode::Counter);
+    let mut car = car.with_distance_bounding(Some(Duration::from_micros(100)));
+    // pressed out of range, then recorded and played back while the next press is ranged
+    let lock = keychain.command(Command::Lock).unwrap();
+    let open = keychain.command(Command::Open).unwrap();
+    let first = car.process(&open).unwrap().unwrap();
+    assert_eq!(car.process(&lock), Err(ProtocolError::Replay));
+    assert!(car.refusal(ProtocolError::Replay).is_some());
+    let success = bound_distance(
+        &mut car,
+        &mut keychain,
+        &clock,
+        first,
+        Duration::from_micros(50),
+        (0, Duration::from_micros(50)),
+    )
+    .unwrap()
+    .unwrap();
+    assert_eq!(car.state().lock, LockState::Unlocked);
+    keychain.process(&success).unwrap();
+    assert!(keychain.confirmed());
+}
+
+// Runs the whole protocol suite against one backend
+macro_rules! backend_tests {
+    ($name:ident, $backend:ty) => {
+        mod $name {
+            #[test]
+            fn replayed_command_open_is_rejected() {
+                super::replayed_command_open_is_rejected::<$backend>();
+            }
+
+            #[test]
+            fn full_replay_cache_refuses_fresh_messages() {
+                super::full_replay_cache_refuses_fresh_messages::<$backend>();
+            }
+
+            #[test]
+            fn counter_resynchronizes_after_jump() {
+                super::counter_resynchronizes_after_jump::<$backend>();
+            }
+
+            #[test]
+            fn counters_survive_a_restart() {
+                super::counters_survive_a_restart::<$backend>();
+            }
+
+            #[test]
+            fn legacy_pkcs1_signatures_are_accepted() {
+                super::legacy_pkcs1_signatures_are_accepted::<$backend>();
+            }
+
+            #[test]
+            fn short_algorithms_fit_radio_frames() {
+                super::short_algorithms_fit_radio_frames::<$backend>();
+            }
+
+            #[test]
+            fn refusals_carry_a_reason() {
+                super::refusals_carry_a_reason::<$backend>();
+            }
+
+            #[test]
+            fn refusals_are_answered_on_the_wire() {
+                super::refusals_are_answered_on_the_wire::<$backend>();
+            }
+
+            #[test]
+            fn success_is_authenticated() {
+                super::success_is_authenticated::<$backend>();
+            }
+
+            #[test]
+            fn frames_are_parsed_strictly() {
+                super::frames_are_parsed_strictly::<$backend>();
+            }
+
+            #[test]
+            fn messages_borrow_from_the_frame() {
+                super::messages_borrow_from_the_frame::<$backend>();
+            }
+
+            #[test]
+            fn broken_key_is_reported() {
+                super::broken_key_is_reported::<$backend>();
+            }
+
+            #[test]
+            fn keys_of_another_algorithm_are_refused() {
+                super::keys_of_another_algorithm_are_refused::<$backend>();
+            }
+
+            #[test]
+            fn devices_talk_over_a_transport() {
+                super::devices_talk_over_a_transport::<$backend>();
+            }
+
+            #[test]
+            fn foreign_challenge_is_ignored() {
+                super::foreign_challenge_is_ignored::<$backend>();
+            }
+
+            #[test]
+            fn frame_with_another_algorithm_is_rejected() {
+                super::frame_with_another_algorithm_is_rejected::<$backend>();
+            }
+
+            #[test]
+            fn commands_drive_car_state() {
+                super::commands_drive_car_state::<$backend>();
+            }
+
+            #[test]
+            fn commands_are_checked_against_state() {
+                super::commands_are_checked_against_state::<$backend>();
+            }
+
+            #[test]
+            fn commands_need_permission() {
+                super::commands_need_permission::<$backend>();
+            }
+
+            #[test]
+            fn reply_for_another_command_is_rejected() {
+                super::reply_for_another_command_is_rejected::<$backend>();
+            }
+
+            #[test]
+            fn car_relocks_when_no_door_opens() {
+                super::car_relocks_when_no_door_opens::<$backend>();
+            }
+
+            #[test]
+            fn transitions_are_reported() {
+                super::transitions_are_reported::<$backend>();
+            }
+
+            #[test]
+            fn timestamp_freshness_follows_the_clock() {
+                super::timestamp_freshness_follows_the_clock::<$backend>();
+            }
+
+            #[test]
+            fn challenge_expires_with_the_clock() {
+                super::challenge_expires_with_the_clock::<$backend>();
+            }
+
+            #[test]
+            fn replay_cache_frees_up_as_the_clock_moves() {
+                super::replay_cache_frees_up_as_the_clock_moves::<$backend>();
+            }
+
+            #[test]
+            fn fragments_survive_reordering_and_duplicates() {
+                super::fragments_survive_reordering_and_duplicates::<$backend>();
+            }
+
+            #[test]
+            fn lost_fragments_time_out() {
+                super::lost_fragments_time_out::<$backend>();
+            }
+
+            #[test]
+            fn keychain_presses_again_until_confirmed() {
+                super::keychain_presses_again_until_confirmed::<$backend>();
+            }
+
+            #[test]
+            fn late_success_does_not_confirm_a_new_press() {
+                super::late_success_does_not_confirm_a_new_press::<$backend>();
+            }
+
+            #[test]
+            fn retries_overcome_a_lossy_channel() {
+                super::retries_overcome_a_lossy_channel::<$backend>();
+            }
+
+            #[test]
+            fn distance_bounding_times_every_round() {
+                super::distance_bounding_times_every_round::<$backend>();
+            }
+
+            #[test]
+            fn stale_commands_leave_ranging_alone() {
+                super::stale_commands_leave_ranging_alone::<$backend>();
+            }
+
+            #[test]
+            fn forward_skew_is_tolerated() {
+                super::forward_skew_is_tolerated::<$backend>();
+            }
+
+            #[test]
+            fn drifting_keychain_is_followed() {
+                super::drifting_keychain_is_followed::<$backend>();
+            }
+
+            #[test]
+            fn time_sync_corrects_a_drifting_keychain() {
+                super::time_sync_corrects_a_drifting_keychain::<$backend>();
+            }
+
+            #[test]
+            fn time_sync_is_authenticated() {
+                super::time_sync_is_authenticated::<$backend>();
+            }
+        }
+    };
+}
+
+#[cfg(feature = "openssl")]
+backend_tests!(openssl_backend, keychain_protocol::crypto::OpenSsl);
+#[cfg(feature = "rustcrypto")]
+backend_tests!(rustcrypto_backend, keychain_protocol::crypto::RustCrypto);
+
+#[test]
+fn ether_reaches_everyone_but_the_sender() {
+    let ether = Ether::new();
+    let (mut a, mut b, mut c) = (ether.connect(), ether.connect(), ether.connect());
+    a.send(Address::BROADCAST, b"everyone").unwrap();
+    b.send(c.address(), b"only c").unwrap();
+    b.send(Address(7), b"nobody").unwrap();
+
+    assert_eq!(a.receive().unwrap(), None);
+    assert_eq!(
+        b.receive().unwrap(),
+        Some((a.address(), b"everyone".to_vec()))
+    );
+    assert_eq!(b.receive().unwrap(), None);
+    assert_eq!(
+        c.receive().unwrap(),
+        Some((a.address(), b"everyone".to_vec()))
+    );
+    assert_eq!(
+        c.receive().unwrap(),
+        Some((b.address(), b"only c".to_vec()))
+    );
+    assert_eq!(c.receive().unwrap(), None);
+    assert_eq!(ether.log().len(), 3);
+}
+
+#[test]
+fn senders_may_share_fragment_ids() {
+    let ether = Ether::new();
+    let mut link = Fragmented::new(ether.connect(), RADIO_MTU, 0x1000);
+    let (mut a, mut b) = (ether.connect(), ether.connect());
+    let frames = [vec![0xa; 100], vec![0xb; 100]];
+    let split = |frame| Fragmenter::new(RADIO_MTU, 0x2000).split(frame);
+    for (from_a, from_b) in split(&frames[0]).iter().zip(&split(&frames[1])) {
+        a.send(Address::BROADCAST, from_a).unwrap();
+        b.send(Address::BROADCAST, from_b).unwrap();
+    }
+    let received: Vec<_> = iter::from_fn(|| link.receive().unwrap()).collect();
+    assert_eq!(
+        received,
+        [
+            (a.address(), frames[0].clone()),
+            (b.address(), frames[1].clone())
+        ]
+    );
+}
+
+#[test]
+fn lossy_ether_delays_loses_and_damages_frames() {
+    let clock = MockClock::new();
+    let channel = Channel {
+        loss: 0.2,
+        latency: Latency::Fixed(Duration::from_millis(5)),
+        bit_error_rate: 0.01,
+        ..Channel::default()
+    };
+    let heard = |seed| {
+        let ether = LossyEther::new(channel, seed, clock.clone());
+        let (mut a, mut b) = (ether.connect(), ether.connect());
+        for _ in 0..100 {
+            a.send(Address::BROADCAST, &[0u8; 8]).unwrap();
+        }
+        assert_eq!(b.receive().unwrap(), None);
+        assert!(ether.in_flight() < 100);
+        assert_eq!(
+            ether.next_arrival(),
+            Some(clock.now() + Duration::from_millis(5))
+        );
+        clock.advance(Duration::from_millis(5));
+        let mut frames = Vec::new();
+        while let Some((from, frame)) = b.receive().unwrap() {
+            assert_eq!(from, a.address());
+            frames.push(frame);
+        }
+        assert_eq!(ether.in_flight(), 0);
+        frames
+    };
+    let frames = heard(1);
+    assert!(frames.len() > 60 && frames.len() < 100);
+    assert!(frames.iter().any(|frame| frame != &[0u8; 8]));
+    assert_eq!(heard(1), frames);
+    assert_ne!(heard(2), frames);
+}
+
+#[cfg(feature = "openssl")]
+#[test]
+fn command_open_verifies_with_standard_verifier() {
+    use keychain_protocol::crypto::OpenSsl;
+    use keychain_protocol::{COMMAND_LENGTH, COUNTER_LENGTH};
+    use openssl::hash::MessageDigest;
+    use openssl::pkey::PKey;
+    use openssl::rsa::Padding;
+
+    let (public_pem, private_pem) = OpenSsl::generate(Algorithm::RsaPss).unwrap();
+    let (car_pem, _) = OpenSsl::generate(Algorithm::RsaPss).unwrap();
+    let mut keychain =
+        Keychain::<OpenSsl>::new(&private_pem, &car_pem, Mode::Counter, Algorithm::RsaPss).unwrap();
+    let message = keychain.get_initiation_message().unwrap();
+    let frame = frame::decode(&message).unwrap();
+    let sign = &frame.payload[COMMAND_LENGTH + COUNTER_LENGTH..];
+    // everything before the signature: header, length, command and counter
+    let signed = &message[..message.len() - frame::CHECKSUM_LENGTH - sign.len()];
+    let key = PKey::public_key_from_pem(&public_pem).unwrap();
+    assert_eq!(sign.len(), key.size());
+
+    let mut verifier = openssl::sign::Verifier::new(MessageDigest::sha256(), &key).unwrap();
+    verifier.set_rsa_padding(Padding::PKCS1_PSS).unwrap();
+    assert!(verifier.verify_oneshot(sign, signed).unwrap());
+}
+
+#[cfg(all(feature = "openssl", feature = "rustcrypto"))]
+fn interoperate<C: Backend, K: Backend>(algorithm: Algorithm) {
+    let (car_public, car_private) = C::generate(algorithm).unwrap();
+    let (keychain_public, keychain_private) = K::generate(algorithm).unwrap();
+    let mut car = Car::<C>::new(&car_private, &keychain_public, Mode::Counter, algorithm).unwrap();
+    let mut keychain =
+        Keychain::<K>::new(&keychain_private, &car_public, Mode::Counter, algorithm).unwrap();
+    let ether: VecDeque<Vec<u8>> = vec![keychain.get_initiation_message().unwrap()].into();
+    assert_eq!(successes(&run(&mut [&mut car, &mut keychain], ether)), 1);
+    assert!(keychain.confirmed());
+}
+
+#[cfg(all(feature = "openssl", feature = "rustcrypto"))]
+#[test]
+fn backends_interoperate() {
+    use keychain_protocol::crypto::{OpenSsl, RustCrypto};
+
+    let algorithms = [
+        Algorithm::RsaPss,
+        Algorithm::RsaPkcs1v15,
+        Algorithm::Ed25519,
+        Algorithm::EcdsaP256,
+    ];
+    for &algorithm in &algorithms {
+        interoperate::<RustCrypto, OpenSsl>(algorithm);
+        interoperate::<OpenSsl, RustCrypto>(algorithm);
+    }
+}
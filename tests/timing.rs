@@ -0,0 +1,248 @@
+// Statistical timing tests in the style of dudect: the car is fed two classes of input in
+// random order, and Welch's t-test on how long it took tells whether its timing depends on
+// the class. Every frame carries a valid signature of the keychain's key and is tampered with
+// in the one field the car compares with what it keeps to itself, nearly right in one class
+// and far off in the other, so how close a forgery came must not show in how soon the car
+// refuses it.
+//
+// They take a while and a busy machine can fail them, so they only run when asked for:
+// `cargo test --test timing -- --ignored`
+
+extern crate keychain_protocol;
+
+use keychain_protocol::clock::MockClock;
+use keychain_protocol::crypto::{Algorithm, Backend, Signer};
+use keychain_protocol::frame::{self, NO_ALGORITHM};
+use keychain_protocol::{
+    Car, Command, Keychain, MessageKind, MessageProcessor, Mode, ProtocolError, COMMAND_LENGTH,
+    COUNTER_LENGTH, NONCE_LENGTH, RANGE_MASK_LENGTH, RANGE_ROUNDS,
+};
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+// The comparisons measured come after the signature is verified, whatever its algorithm
+const ALGORITHM: Algorithm = Algorithm::Ed25519;
+// Measurements per test, both classes together, after the ones warming up caches
+const SAMPLES: usize = 10_000;
+const WARM_UP: usize = 500;
+// |t| above which dudect considers a leak certain, lower values show up on a busy machine
+const LEAK: f64 = 10.0;
+// Commands in the replay cache when replays are looked up
+const CACHED: usize = 1024;
+
+// Held while measuring, tests running side by side would add each other's noise
+static MEASURING: Mutex<()> = Mutex::new(());
+
+// A car, its keychain and the keychain's key to forge with
+fn pair<B: Backend>(mode: Mode) -> (Car<B>, Keychain<B>, B::Signer) {
+    let (car_public, car_private) = B::generate(ALGORITHM).unwrap();
+    let (keychain_public, keychain_private) = B::generate(ALGORITHM).unwrap();
+    (
+        Car::new(&car_private, &keychain_public, mode, ALGORITHM).unwrap(),
+        Keychain::new(&keychain_private, &car_public, mode, ALGORITHM).unwrap(),
+        B::signer(ALGORITHM, &keychain_private).unwrap(),
+    )
+}
+
+// A frame of `kind` carrying `body` and a signature over it and `bound`, as the keychain
+// would send it
+fn signed<S: Signer>(signer: &S, kind: MessageKind, body: &[u8], bound: &[u8]) -> Vec<u8> {
+    let mut frame = frame::start(
+        kind,
+        ALGORITHM as u8,
+        body.len() + signer.signature_length(),
+    );
+    frame.extend_from_slice(body);
+    let signature = signer.sign(&[&frame[..], bound].concat()).unwrap();
+    frame.extend_from_slice(&signature);
+    frame::finish(frame)
+}
+
+// `bits` with one of them, picked at random, flipped
+fn near_miss<B: Backend>(bits: &[u8]) -> Vec<u8> {
+    let mut pick = [0u8; 2];
+    B::random(&mut pick);
+    let flipped = u16::from_be_bytes(pick) as usize % (8 * bits.len());
+    let mut near = bits.to_vec();
+    near[flipped / 8] ^= 1 << (flipped % 8);
+    near
+}
+
+// Random bits as long as `bits`, but never equal to them
+fn far_miss<B: Backend>(bits: &[u8]) -> Vec<u8> {
+    let mut far = bits.to_vec();
+    while far == bits {
+        B::random(&mut far);
+    }
+    far
+}
+
+// Welch's t statistic of two sets of measurements
+fn welch_t(a: &[f64], b: &[f64]) -> f64 {
+    let stats = |x: &[f64]| {
+        let n = x.len() as f64;
+        let mean = x.iter().sum::<f64>() / n;
+        let variance = x.iter().map(|v| (v - mean) * (v - mean)).sum::<f64>() / (n - 1.0);
+        (n, mean, variance)
+    };
+    let ((na, ma, va), (nb, mb, vb)) = (stats(a), stats(b));
+    let spread = (va / na + vb / nb).sqrt();
+    if spread == 0.0 {
+        return 0.0;
+    }
+    (ma - mb) / spread
+}
+
+// Times `car` processing a frame of one class at a time, picked at random and made by
+// `input` beforehand, each refused with `expected`. Returns the largest |t| over all
+// measurements and over the ones below a few percentiles, cropped as dudect does to look
+// past interrupts.
+fn leakage<B: Backend>(
+    car: &mut Car<B>,
+    expected: ProtocolError,
+    mut input: impl FnMut(&mut Car<B>, usize) -> Vec<u8>,
+) -> f64 {
+    let _turn = MEASURING
+        .lock()
+        .unwrap_or_else(|poisoned| poisoned.into_inner());
+    let mut picks = vec![0u8; WARM_UP + SAMPLES];
+    B::random(&mut picks);
+    let mut measured: Vec<(usize, f64)> = Vec::with_capacity(SAMPLES);
+    for (sample, pick) in picks.iter().enumerate() {
+        let class = (pick & 1) as usize;
+        let frame = input(car, class);
+        let start = Instant::now();
+        let result = car.process(&frame);
+        let elapsed = start.elapsed();
+        assert_eq!(result, Err(expected));
+        if sample >= WARM_UP {
+            measured.push((class, elapsed.as_nanos() as f64));
+        }
+    }
+
+    let mut sorted: Vec<f64> = measured.iter().map(|(_, time)| *time).collect();
+    sorted.sort_by(|a, b| a.partial_cmp(b).unwrap());
+    [1.0, 0.9, 0.75, 0.5]
+        .iter()
+        .map(|percentile| {
+            let limit = sorted[((sorted.len() - 1) as f64 * percentile) as usize];
+            let class = |class: usize| -> Vec<f64> {
+                measured
+                    .iter()
+                    .filter(|(c, time)| *c == class && *time <= limit)
+                    .map(|(_, time)| *time)
+                    .collect()
+            };
+            welch_t(&class(0), &class(1)).abs()
+        })
+        .fold(0.0, f64::max)
+}
+
+// Commands signed over a nonce one bit off the car's outstanding challenge against ones
+// signed over a random nonce, a fresh challenge for every sample
+fn challenge_comparison_takes_as_long_however_close<B: Backend>() {
+    let (mut car, _, signer) = pair::<B>(Mode::Challenge);
+    let hello = frame::encode(MessageKind::Hello, NO_ALGORITHM, &[]);
+    let t = leakage(&mut car, ProtocolError::NoChallenge, |car, class| {
+        let challenge = car.process(&hello).unwrap().unwrap();
+        let nonce = &frame::decode(&challenge).unwrap().payload[..NONCE_LENGTH];
+        let token = if class == 0 {
+            near_miss::<B>(nonce)
+        } else {
+            far_miss::<B>(nonce)
+        };
+        let body = [&[Command::Open as u8][..], &token].concat();
+        signed(&signer, MessageKind::Command, &body, &[])
+    });
+    assert!(t < LEAK, "|t| = {:.1}", t);
+}
+
+// RangeProofs signed over every bit of a distance bounding exchange, revealing a mask one bit
+// off the one the keychain answered with against a random one, a fresh exchange for every
+// sample
+fn range_comparison_takes_as_long_however_close<B: Backend>() {
+    let (car, mut keychain, signer) = pair::<B>(Mode::Counter);
+    let mut car = car.with_distance_bounding(Some(Duration::from_secs(1)));
+    let t = leakage(&mut car, ProtocolError::OutOfRange, |car, class| {
+        let command = keychain.command(Command::Open).unwrap();
+        let answering =
+            &frame::decode(&command).unwrap().payload[..COMMAND_LENGTH + COUNTER_LENGTH];
+        let mut challenges = [0u8; RANGE_MASK_LENGTH];
+        let mut responses = [0u8; RANGE_MASK_LENGTH];
+        let mut challenge = car.process(&command).unwrap().unwrap();
+        for round in 0..RANGE_ROUNDS {
+            let response = keychain.process(&challenge).unwrap().unwrap();
+            challenges[round / 8] |= frame::decode(&challenge).unwrap().payload[1] << (round % 8);
+            responses[round / 8] |= frame::decode(&response).unwrap().payload[1] << (round % 8);
+            challenge = car.process(&response).unwrap().unwrap();
+        }
+        let mask: Vec<u8> = challenges
+            .iter()
+            .zip(&responses)
+            .map(|(c, r)| c ^ r)
+            .collect();
+        let forged = if class == 0 {
+            near_miss::<B>(&mask)
+        } else {
+            far_miss::<B>(&mask)
+        };
+        let bound = [answering, &challenges, &responses].concat();
+        signed(&signer, MessageKind::RangeProof, &forged, &bound)
+    });
+    assert!(t < LEAK, "|t| = {:.1}", t);
+}
+
+// Replays of the oldest Command in a full replay cache against replays of the newest, the
+// car must not find one sooner than the other. The cache is larger than by default so that
+// a lookup stopping at the first match would stand out from the signature verification.
+fn replay_lookup_takes_as_long_wherever_the_match<B: Backend>() {
+    let (car, keychain, _) = pair::<B>(Mode::Timestamp);
+    let clock = MockClock::new();
+    clock.advance(Duration::from_secs(1_600_000_000));
+    let mut car = car
+        .with_clock(Box::new(clock.clone()))
+        .with_replay_capacity(CACHED);
+    let mut keychain = keychain.with_clock(Box::new(clock.clone()));
+    let mut commands = Vec::new();
+    for _ in 0..CACHED {
+        // a timestamp of its own for every Command, the mock clock stands still otherwise
+        clock.advance(Duration::from_nanos(1));
+        let command = keychain.command(Command::Open).unwrap();
+        let _ = car.process(&command);
+        commands.push(command);
+    }
+    let (oldest, newest) = (&commands[0], &commands[CACHED - 1]);
+    let t = leakage(&mut car, ProtocolError::Replay, |_, class| {
+        if class == 0 { oldest } else { newest }.clone()
+    });
+    assert!(t < LEAK, "|t| = {:.1}", t);
+}
+
+macro_rules! backend_tests {
+    ($name:ident, $backend:ty) => {
+        mod $name {
+            #[test]
+            #[ignore]
+            fn challenge_comparison_takes_as_long_however_close() {
+                super::challenge_comparison_takes_as_long_however_close::<$backend>();
+            }
+
+            #[test]
+            #[ignore]
+            fn range_comparison_takes_as_long_however_close() {
+                super::range_comparison_takes_as_long_however_close::<$backend>();
+            }
+
+            #[test]
+            #[ignore]
+            fn replay_lookup_takes_as_long_wherever_the_match() {
+                super::replay_lookup_takes_as_long_wherever_the_match::<$backend>();
+            }
+        }
+    };
+}
+
+#[cfg(feature = "openssl")]
+backend_tests!(openssl_backend, keychain_protocol::crypto::OpenSsl);
+#[cfg(feature = "rustcrypto")]
+backend_tests!(rustcrypto_backend, keychain_protocol::crypto::RustCrypto);
@@ -0,0 +1,56 @@
+extern crate keychain_protocol;
+
+mod common;
+
+use common::{car, confirmed, keychain, Scratch};
+
+#[test]
+fn car_and_keychain_talk_over_udp() {
+    let keys = Scratch::new("talk");
+    let paired = car(&keys, &["--generate"]);
+    let port = paired.address().to_string();
+    let output = keychain(&keys, &["--peer", &port, "open", "lock", "enginestart"]);
+    assert!(output.status.success());
+    assert_eq!(confirmed(&output), 3);
+
+    let output = keychain(&keys, &["--peer", &port, "enginestart"]);
+    assert_eq!(output.status.code(), Some(1));
+}
+
+#[test]
+fn timestamp_mode_works_across_processes() {
+    let keys = Scratch::new("timestamp");
+    let mode = ["--mode", "timestamp", "--algorithm", "ecdsap256"];
+    let paired = car(&keys, &[&["--generate"][..], &mode].concat());
+    let port = paired.address().to_string();
+    for _ in 0..2 {
+        let output = keychain(&keys, &[&["--peer", &port][..], &mode].concat());
+        assert!(output.status.success());
+    }
+}
+
+#[test]
+fn counter_mode_carries_on_across_keychain_runs() {
+    let keys = Scratch::new("counter");
+    let mode = ["--mode", "counter"];
+    let paired = car(&keys, &[&["--generate"][..], &mode].concat());
+    let port = paired.address().to_string();
+    for _ in 0..2 {
+        let output = keychain(&keys, &[&["--peer", &port][..], &mode].concat());
+        assert!(output.status.success());
+    }
+    let saved = std::fs::read_to_string(keys.path("keychain.counter")).unwrap();
+    assert_eq!(saved, "2");
+}
+
+#[test]
+fn foreign_keychain_is_ignored() {
+    let keys = Scratch::new("foreign");
+    let other = Scratch::new("foreign-other");
+    let paired = car(&keys, &["--generate"]);
+    let port = paired.address().to_string();
+    // another car with its own keys, its keychain means nothing to ours
+    drop(car(&other, &["--generate"]));
+    let output = keychain(&other, &["--peer", &port]);
+    assert_eq!(output.status.code(), Some(1));
+}